@@ -6,6 +6,8 @@ console.log("📦 Importing required modules...");
 type Client = {
   ws: ServerWebSocket<WebSocketData>;
   id: string;
+  joinedAt: number;
+  lastActiveAt: number;
 };
 
 type WebSocketData = {
@@ -13,12 +15,37 @@ type WebSocketData = {
 };
 
 type ChatMessage = {
+  // Set once the message is stored
+  id?: number;
   content: string;
   authorId: string;
   timestamp: number;
 };
 
-console.log("📝 Defined types for Client, WebSocketData, and ChatMessage");
+type TypingEvent = {
+  type: "typing";
+  authorId: string;
+  typing: boolean;
+};
+
+type PresenceUser = {
+  id: string;
+  joinedAt: number;
+  lastActiveAt: number;
+};
+
+type PresenceEvent =
+  | { type: "presence"; users: PresenceUser[] }
+  | { type: "join"; user: PresenceUser }
+  | { type: "leave"; userId: string };
+
+type MessageChangeEvent =
+  | { type: "edit"; id: number; content: string }
+  | { type: "delete"; id: number };
+
+console.log(
+  "📝 Defined types for Client, WebSocketData, ChatMessage, TypingEvent, PresenceEvent and MessageChangeEvent",
+);
 
 const clients: Client[] = [];
 console.log("👥 Initialized empty clients array");
@@ -84,7 +111,7 @@ console.log("📚 Fetching recent messages from database...");
 const recentMessages = db
   .query(
     `
-  SELECT content, authorId, timestamp
+  SELECT id, content, authorId, timestamp
   FROM messages
   ORDER BY timestamp DESC
   LIMIT 5
@@ -109,7 +136,10 @@ const server = Bun.serve<WebSocketData, { userid: any }>({
     if (url.pathname === "/users") {
       console.log("👥 Users list requested");
       return new Response(
-        JSON.stringify({ count: clients.length, users: clients }),
+        JSON.stringify({
+          count: clients.length,
+          users: clients.map(toPresenceUser),
+        }),
         {
           headers: { "Content-Type": "application/json" },
         },
@@ -180,10 +210,25 @@ const server = Bun.serve<WebSocketData, { userid: any }>({
 
   websocket: {
     open(ws) {
-      clients.push({ ws, id: ws.data.userId });
+      const now = Date.now();
+      const client: Client = {
+        ws,
+        id: ws.data.userId,
+        joinedAt: now,
+        lastActiveAt: now,
+      };
+      clients.push(client);
       console.log(`\n🟢 Client connected: ${ws.data.userId}`);
       console.log(`👥 Total clients: ${clients.length}`);
 
+      const presence: PresenceEvent = {
+        type: "presence",
+        users: clients.map(toPresenceUser),
+      };
+      ws.send(JSON.stringify(presence));
+      broadcastMessage({ type: "join", user: toPresenceUser(client) });
+      console.log(`📇 Presence list sent to: ${ws.data.userId}`);
+
       console.log(`📤 Sending message history to ${ws.data.userId}`);
       for (const m of recentMessages.slice(0, 5)) {
         ws.send(JSON.stringify(m));
@@ -202,8 +247,8 @@ const server = Bun.serve<WebSocketData, { userid: any }>({
         authorId: "system",
         timestamp: Date.now(),
       };
-      broadcastMessage(joinMessage);
       addToRecentMessages(joinMessage);
+      broadcastMessage(joinMessage);
       console.log(`🎉 Welcome message sent for: ${ws.data.userId}`);
     },
 
@@ -213,6 +258,30 @@ const server = Bun.serve<WebSocketData, { userid: any }>({
         const data = JSON.parse(message.toString());
         console.log(`🔍 Parsed message data: ${JSON.stringify(data)}`);
 
+        const sender = clients.find((client) => client.ws === ws);
+        if (sender) {
+          sender.lastActiveAt = Date.now();
+        }
+
+        if (data.type === "typing") {
+          const typingEvent: TypingEvent = {
+            type: "typing",
+            authorId: ws.data.userId,
+            typing: Boolean(data.typing),
+          };
+          // Typing notices are ephemeral: relay to everyone else, never store
+          broadcastMessage(typingEvent, ws);
+          console.log(
+            `✍️ ${ws.data.userId} ${typingEvent.typing ? "started" : "stopped"} typing`,
+          );
+          return;
+        }
+
+        if (data.type === "edit" || data.type === "delete") {
+          changeMessage(ws, data);
+          return;
+        }
+
         if (!data.content) {
           console.warn(`⚠️ Invalid message format from: ${ws.data.userId}`);
           ws.send(JSON.stringify({ error: "Invalid message format" }));
@@ -249,13 +318,15 @@ const server = Bun.serve<WebSocketData, { userid: any }>({
       console.log(`\n🔴 Client disconnected: ${ws.data.userId}`);
       console.log(`👥 Total clients: ${clients.length}`);
 
+      broadcastMessage({ type: "leave", userId: ws.data.userId });
+
       const leaveMessage: ChatMessage = {
         content: `${ws.data.userId} left the chat`,
         authorId: "system",
         timestamp: Date.now(),
       };
-      broadcastMessage(leaveMessage);
       addToRecentMessages(leaveMessage);
+      broadcastMessage(leaveMessage);
       console.log(`👋 Goodbye message sent for: ${ws.data.userId}`);
     },
   },
@@ -266,21 +337,42 @@ function addToRecentMessages(message: ChatMessage) {
     `\n📥 Adding new message to database: ${JSON.stringify(message)}`,
   );
   // Insert new message into database
-  db.run(
+  const inserted = db.run(
     `
     INSERT INTO messages (content, authorId, timestamp)
     VALUES (?, ?, ?)
   `,
     [message.content, message.authorId, message.timestamp],
   );
-  console.log("✅ Message inserted into database");
+  message.id = Number(inserted.lastInsertRowid);
+  console.log(`✅ Message inserted into database with id ${message.id}`);
+
+  refreshRecentMessages();
 
-  // Keep only the latest 5 messages in memory
+  // Delete old messages from database, keeping only latest 5
+  console.log("🗑️ Cleaning up old messages from database");
+  db.run(`
+    DELETE FROM messages
+    WHERE id NOT IN (
+      SELECT id FROM messages
+      ORDER BY timestamp DESC
+      LIMIT 5
+    )
+  `);
+  console.log("✅ Old messages cleaned up");
+
+  console.log(
+    `📝 Message added to history (Total: ${recentMessages.length}/${MAX_RECENT_MESSAGES})`,
+  );
+}
+
+// Keep only the latest 5 messages in memory
+function refreshRecentMessages() {
   console.log("🔄 Updating recent messages in memory");
   const latestMessages = db
     .query(
       `
-    SELECT content, authorId, timestamp
+    SELECT id, content, authorId, timestamp
     FROM messages
     ORDER BY timestamp DESC
     LIMIT 5
@@ -295,30 +387,66 @@ function addToRecentMessages(message: ChatMessage) {
   recentMessages.length = 0;
   recentMessages.push(...(latestMessages as ChatMessage[]));
   console.log("✅ Recent messages array updated");
+}
 
-  // Delete old messages from database, keeping only latest 5
-  console.log("🗑️ Cleaning up old messages from database");
-  db.run(`
-    DELETE FROM messages
-    WHERE id NOT IN (
-      SELECT id FROM messages
-      ORDER BY timestamp DESC
-      LIMIT 5
-    )
-  `);
-  console.log("✅ Old messages cleaned up");
+// Edits or deletes a stored message, but only for the user who sent it
+function changeMessage(
+  ws: ServerWebSocket<WebSocketData>,
+  data: { type: "edit" | "delete"; id: unknown; content?: unknown },
+) {
+  const id = Number(data.id);
+  const stored = db
+    .query("SELECT authorId FROM messages WHERE id = ?")
+    .get(id) as { authorId: string } | null;
+  if (!stored) {
+    console.warn(`⚠️ ${ws.data.userId} tried to change unknown message ${id}`);
+    ws.send(JSON.stringify({ error: "That message is too old to change" }));
+    return;
+  }
+  if (stored.authorId !== ws.data.userId) {
+    console.warn(`⚠️ ${ws.data.userId} tried to change message ${id}`);
+    ws.send(JSON.stringify({ error: "You can only change your own messages" }));
+    return;
+  }
 
-  console.log(
-    `📝 Message added to history (Total: ${recentMessages.length}/${MAX_RECENT_MESSAGES})`,
-  );
+  let change: MessageChangeEvent;
+  if (data.type === "edit") {
+    if (typeof data.content !== "string" || !data.content.trim()) {
+      ws.send(JSON.stringify({ error: "Invalid message format" }));
+      return;
+    }
+    db.run("UPDATE messages SET content = ? WHERE id = ?", [data.content, id]);
+    change = { type: "edit", id, content: data.content };
+    console.log(`✏️ ${ws.data.userId} edited message ${id}`);
+  } else {
+    db.run("DELETE FROM messages WHERE id = ?", [id]);
+    change = { type: "delete", id };
+    console.log(`🗑️ ${ws.data.userId} deleted message ${id}`);
+  }
+  refreshRecentMessages();
+  broadcastMessage(change);
 }
 
-function broadcastMessage(message: ChatMessage) {
+function toPresenceUser(client: Client): PresenceUser {
+  return {
+    id: client.id,
+    joinedAt: client.joinedAt,
+    lastActiveAt: client.lastActiveAt,
+  };
+}
+
+function broadcastMessage(
+  message: ChatMessage | TypingEvent | PresenceEvent | MessageChangeEvent,
+  except?: ServerWebSocket<WebSocketData>,
+) {
   const messageStr = JSON.stringify(message);
   console.log(`\n📢 Broadcasting message to ${clients.length} clients`);
   console.log(`📦 Message content: ${messageStr}`);
 
   for (const client of clients) {
+    if (client.ws === except) {
+      continue;
+    }
     client.ws.send(messageStr);
     console.log(`  ↪️ Sent to: ${client.id}`);
   }
@@ -0,0 +1,139 @@
+use ratatui::widgets::ListState;
+
+use crate::transcript::Message;
+
+// Quick reactions offered by the React action
+pub const REACTIONS: [&str; 8] = ["👍", "❤️", "😂", "🔥", "😮", "😢", "🙏", "💯"];
+
+// Lines of the original kept when quoting it in a reply or reaction
+const EXCERPT_LINES: usize = 3;
+
+// Something to do with the selected message
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageAction {
+    Reply,
+    React,
+    Copy,
+    CopyQuote,
+    OpenLinks,
+    Details,
+    Edit,
+    Delete,
+}
+
+impl MessageAction {
+    pub const ALL: [MessageAction; 8] = [
+        MessageAction::Reply,
+        MessageAction::React,
+        MessageAction::Copy,
+        MessageAction::CopyQuote,
+        MessageAction::OpenLinks,
+        MessageAction::Details,
+        MessageAction::Edit,
+        MessageAction::Delete,
+    ];
+
+    // Shortcut in selection mode and in the menu
+    pub fn key(self) -> char {
+        match self {
+            MessageAction::Reply => 'r',
+            MessageAction::React => '+',
+            MessageAction::Copy => 'y',
+            MessageAction::CopyQuote => 'Y',
+            MessageAction::OpenLinks => 'o',
+            MessageAction::Details => 'i',
+            MessageAction::Edit => 'e',
+            MessageAction::Delete => 'd',
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            MessageAction::Reply => "Reply",
+            MessageAction::React => "React",
+            MessageAction::Copy => "Copy text",
+            MessageAction::CopyQuote => "Copy as quote",
+            MessageAction::OpenLinks => "Open links",
+            MessageAction::Details => "View details",
+            MessageAction::Edit => "Edit",
+            MessageAction::Delete => "Delete",
+        }
+    }
+
+    // What makes sense for `message`: notices can only be copied and inspected, and only
+    // your own messages that the server knows about can be edited or deleted
+    pub fn available(message: &Message, has_links: bool) -> Vec<MessageAction> {
+        Self::ALL
+            .into_iter()
+            .filter(|action| match action {
+                _ if message.deleted => *action == MessageAction::Details,
+                MessageAction::Copy | MessageAction::Details => true,
+                MessageAction::OpenLinks => has_links,
+                MessageAction::Edit | MessageAction::Delete => {
+                    message.from_user && message.server_id.is_some()
+                }
+                _ => message.is_chat(),
+            })
+            .collect()
+    }
+}
+
+// The popup over a selected message
+pub struct MessageMenu {
+    // Index of the message in the transcript
+    pub index: usize,
+    pub items: MenuItems,
+    pub state: ListState,
+}
+
+pub enum MenuItems {
+    Actions(Vec<MessageAction>),
+    Reactions,
+    // Delete asks once more, since it can't be undone
+    ConfirmDelete,
+}
+
+impl MessageMenu {
+    pub fn new(index: usize, items: MenuItems) -> Self {
+        Self {
+            index,
+            items,
+            state: ListState::default().with_selected(Some(0)),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.items {
+            MenuItems::Actions(actions) => actions.len(),
+            MenuItems::Reactions => REACTIONS.len(),
+            MenuItems::ConfirmDelete => 2,
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        let last = self.len().saturating_sub(1);
+        self.state
+            .select(self.state.selected().map(|index| (index + 1).min(last)));
+    }
+}
+
+// `text` as a Markdown block quote
+pub fn quote(text: &str) -> String {
+    text.lines()
+        .map(|line| match line {
+            "" => String::from(">"),
+            line => format!("> {}", line),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// The first few lines of `text`, marking anything cut off
+pub fn excerpt(text: &str) -> String {
+    let mut lines: Vec<&str> = text.lines().take(EXCERPT_LINES + 1).collect();
+    if lines.len() > EXCERPT_LINES {
+        lines.truncate(EXCERPT_LINES);
+        lines.push("…");
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,61 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+// Copies text to the system clipboard with an OSC 52 escape sequence, which the
+// terminal handles even when we're running over SSH. With `fallback`, it's also piped
+// to wl-copy or xclip when there's a local display, for terminals that ignore OSC 52.
+pub fn copy(text: &str, fallback: bool) -> io::Result<()> {
+    let sequence = format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+    let mut stdout = io::stdout();
+    stdout.write_all(sequence.as_bytes())?;
+    // tmux only forwards it with `set-clipboard on`; the passthrough wrapping gets it
+    // through with `allow-passthrough on` instead
+    if std::env::var_os("TMUX").is_some() {
+        write!(
+            stdout,
+            "\x1bPtmux;{}\x1b\\",
+            sequence.replace('\x1b', "\x1b\x1b")
+        )?;
+    }
+    stdout.flush()?;
+
+    if fallback {
+        // Best effort: OSC 52 has already gone out
+        let _ = copy_with_tool(text);
+    }
+    Ok(())
+}
+
+// Hands the text to the first clipboard tool that fits the session and is installed
+fn copy_with_tool(text: &str) -> io::Result<()> {
+    let mut tools: Vec<&[&str]> = Vec::new();
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.push(&["wl-copy"]);
+    }
+    if std::env::var_os("DISPLAY").is_some() {
+        tools.push(&["xclip", "-selection", "clipboard"]);
+    }
+
+    for tool in tools {
+        // Their output would land on top of the UI
+        let child = Command::new(tool[0])
+            .args(&tool[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        // Both fork into the background to serve the selection, so this returns quickly
+        child.wait()?;
+        return Ok(());
+    }
+    Ok(())
+}
@@ -0,0 +1,166 @@
+// Everything you can type after a `/` instead of a message
+pub struct Spec {
+    pub name: &'static str,
+    pub args: &'static str,
+    pub about: &'static str,
+}
+
+pub const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "help",
+        args: "",
+        about: "list the commands",
+    },
+    Spec {
+        name: "users",
+        args: "",
+        about: "toggle who's online",
+    },
+    Spec {
+        name: "whois",
+        args: "<user>",
+        about: "show someone's card",
+    },
+    Spec {
+        name: "links",
+        args: "",
+        about: "pick a link to open",
+    },
+    Spec {
+        name: "copy",
+        args: "[code|message|screen]",
+        about: "copy a code block, a message or what's on screen",
+    },
+    Spec {
+        name: "theme",
+        args: "[name]",
+        about: "switch colors, or list the themes",
+    },
+    Spec {
+        name: "compact",
+        args: "",
+        about: "toggle the dense IRC-style layout",
+    },
+    Spec {
+        name: "mouse",
+        args: "",
+        about: "toggle mouse capture",
+    },
+    Spec {
+        name: "quit",
+        args: "",
+        about: "rage quit",
+    },
+];
+
+pub enum Command {
+    Help,
+    Users,
+    Whois(String),
+    Links,
+    Copy(CopyTarget),
+    Theme(Option<String>),
+    Compact,
+    Mouse,
+    Quit,
+}
+
+// What /copy puts on the clipboard
+pub enum CopyTarget {
+    // The latest code block at or above the focused message
+    Code,
+    // The selected message, or the one at the bottom of the view
+    Message,
+    // Every message in view, as plain text
+    Screen,
+}
+
+// Parses `/name args`, or None when the input is an ordinary message. Only known names
+// count as commands, so `/shrug` or `/usr/bin is full` go out as typed; a leading `//`
+// sends the rest as a message even when it looks like a command.
+pub fn parse(input: &str) -> Option<Result<Command, String>> {
+    let rest = input.trim().strip_prefix('/')?;
+    if rest.starts_with('/') {
+        return None;
+    }
+    let (name, args) = rest
+        .split_once(char::is_whitespace)
+        .map_or((rest, ""), |(name, args)| (name, args.trim()));
+
+    Some(match name {
+        "help" => Ok(Command::Help),
+        "users" => Ok(Command::Users),
+        "whois" if args.is_empty() => Err("Usage: /whois <user>".to_string()),
+        "whois" => Ok(Command::Whois(args.trim_start_matches('@').to_string())),
+        "links" => Ok(Command::Links),
+        "copy" => match args {
+            "" | "code" => Ok(Command::Copy(CopyTarget::Code)),
+            "message" => Ok(Command::Copy(CopyTarget::Message)),
+            "screen" => Ok(Command::Copy(CopyTarget::Screen)),
+            _ => Err("Usage: /copy [code|message|screen]".to_string()),
+        },
+        "theme" if args.is_empty() => Ok(Command::Theme(None)),
+        "theme" => Ok(Command::Theme(Some(args.to_string()))),
+        "compact" => Ok(Command::Compact),
+        "mouse" => Ok(Command::Mouse),
+        "quit" => Ok(Command::Quit),
+        _ => return None,
+    })
+}
+
+// The message to send for input that isn't a command, dropping the escaping `/` of `//`
+pub fn unescape(input: &str) -> &str {
+    match input.trim_start().strip_prefix("//") {
+        Some(rest) => &input[input.len() - rest.len() - 1..],
+        None => input,
+    }
+}
+
+// One-line summary of every command for /help
+pub fn help() -> String {
+    COMMANDS
+        .iter()
+        .map(|spec| format!("/{}", spec.name))
+        .collect::<Vec<_>>()
+        .join("  ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_commands() {
+        assert!(matches!(parse("/help"), Some(Ok(Command::Help))));
+        assert!(matches!(
+            parse("  /whois @ada "),
+            Some(Ok(Command::Whois(name))) if name == "ada"
+        ));
+        assert!(matches!(
+            parse("/copy screen"),
+            Some(Ok(Command::Copy(CopyTarget::Screen)))
+        ));
+        assert!(matches!(parse("/theme"), Some(Ok(Command::Theme(None)))));
+    }
+
+    #[test]
+    fn rejects_bad_arguments_to_known_commands() {
+        assert!(matches!(parse("/whois"), Some(Err(_))));
+        assert!(matches!(parse("/copy everything"), Some(Err(_))));
+    }
+
+    #[test]
+    fn sends_unknown_slash_words_as_messages() {
+        for input in ["/shrug", "/usr/bin is full", "/", "hello /help"] {
+            assert!(parse(input).is_none(), "{}", input);
+            assert_eq!(unescape(input), input);
+        }
+    }
+
+    #[test]
+    fn double_slash_sends_a_command_as_text() {
+        assert!(parse("//help").is_none());
+        assert_eq!(unescape("//help me"), "/help me");
+        assert_eq!(unescape("  //quit"), "/quit");
+    }
+}
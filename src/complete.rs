@@ -0,0 +1,101 @@
+use crate::commands::COMMANDS;
+use crate::emoji;
+
+// Something Tab can put in place of the word being completed
+pub struct Candidate {
+    pub replacement: String,
+    pub label: String,
+}
+
+// Tab-completion state, kept while the user keeps pressing Tab
+pub struct Completion {
+    // Byte offset where the completed word starts
+    pub start: usize,
+    // Byte offset where the candidate now in the input ends
+    pub end: usize,
+    // What was typed before the first Tab, put back on Esc
+    pub original: String,
+    pub candidates: Vec<Candidate>,
+    pub selected: usize,
+}
+
+impl Completion {
+    pub fn current(&self) -> &Candidate {
+        &self.candidates[self.selected]
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.candidates.len();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self
+            .selected
+            .checked_sub(1)
+            .unwrap_or(self.candidates.len() - 1);
+    }
+}
+
+// Candidates for the word right before `cursor` (a byte offset): `@user` mentions,
+// `/command` names at the start of the input, and `:emoji:` shortcodes
+pub fn candidates(
+    input: &str,
+    cursor: usize,
+    users: &[String],
+    recent: &emoji::Recent,
+) -> Option<(usize, Vec<Candidate>)> {
+    let before = &input[..cursor];
+    let start = before.rfind(char::is_whitespace).map_or(0, |index| {
+        index + before[index..].chars().next().unwrap().len_utf8()
+    });
+    let word = &before[start..];
+
+    let candidates: Vec<Candidate> = if let Some(query) = word.strip_prefix('@') {
+        let query = query.to_lowercase();
+        let mut matches: Vec<&String> = users
+            .iter()
+            .filter(|user| user.to_lowercase().contains(&query))
+            .collect();
+        // Names starting with what was typed come first
+        matches.sort_by_key(|user| !user.to_lowercase().starts_with(&query));
+        matches
+            .into_iter()
+            .map(|user| Candidate {
+                replacement: format!("@{} ", user),
+                label: format!("@{}", user),
+            })
+            .collect()
+    } else if let Some(query) = word.strip_prefix('/').filter(|_| start == 0) {
+        COMMANDS
+            .iter()
+            .filter(|spec| spec.name.starts_with(query))
+            .map(|spec| Candidate {
+                replacement: format!("/{} ", spec.name),
+                label: match spec.args {
+                    "" => format!("/{} – {}", spec.name, spec.about),
+                    args => format!("/{} {} – {}", spec.name, args, spec.about),
+                },
+            })
+            .collect()
+    } else if let Some(query) = word.strip_prefix(':') {
+        let query = query.strip_suffix(':').unwrap_or(query);
+        if !query.chars().all(emoji::is_shortcode_char) {
+            return None;
+        }
+        emoji::search(query, recent)
+            .into_iter()
+            .map(|emoji| Candidate {
+                replacement: emoji.as_str().to_string(),
+                label: format!(
+                    "{} :{}:",
+                    emoji.as_str(),
+                    emoji.shortcode().unwrap_or(emoji.name())
+                ),
+            })
+            .collect()
+    } else {
+        return None;
+    };
+
+    (!candidates.is_empty()).then_some((start, candidates))
+}
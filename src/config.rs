@@ -0,0 +1,99 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
+use serde::Deserialize;
+
+use crate::keymap::{KeyConfig, Keymap};
+use crate::transcript::LayoutStyle;
+
+// User settings from config.toml; anything left out keeps its default
+#[derive(Deserialize, Debug)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    // Most lines the input box grows to before it starts scrolling
+    pub input_height: u16,
+    // Characters a message can have before the input counter warns about it
+    pub message_limit: usize,
+    // Vi-style modal editing: Esc drops into normal mode with motions and operators
+    pub vi_mode: bool,
+    // Mouse capture: wheel scrolling and clicking; off leaves selection to the terminal
+    pub mouse: bool,
+    // Built-in theme name, or a file in themes/ next to this config
+    pub theme: String,
+    // Also copy with wl-copy or xclip when they're installed, on top of OSC 52
+    pub clipboard_fallback: bool,
+    // Bubbles, or compact IRC-style lines
+    pub layout: LayoutStyle,
+    // Show who's online on wide terminals without pressing `u`
+    pub auto_sidebar: bool,
+    // Widest a bubble's text gets, so lines stay readable on wide terminals; 0 for no limit
+    pub max_bubble_width: usize,
+    // Rebound keys, as written under [keys.normal] and [keys.editing]
+    pub keys: KeyConfig,
+    // The keys merged over the defaults, checked for conflicts when the config is loaded
+    #[serde(skip)]
+    pub keymap: Keymap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            input_height: 6,
+            message_limit: 1000,
+            vi_mode: false,
+            mouse: true,
+            theme: "dark".to_string(),
+            clipboard_fallback: true,
+            layout: LayoutStyle::default(),
+            auto_sidebar: true,
+            max_bubble_width: 100,
+            keys: KeyConfig::default(),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+// Reads the config file, falling back to defaults when there isn't one
+pub fn load() -> Result<Config> {
+    let Some(path) = config_dir().map(|dir| dir.join("config.toml")) else {
+        return Ok(Config::default());
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+        Err(e) => return Err(e).wrap_err_with(|| format!("reading {}", path.display())),
+    };
+    let mut config: Config =
+        toml::from_str(&text).wrap_err_with(|| format!("parsing {}", path.display()))?;
+    config.input_height = config.input_height.max(1);
+    config.message_limit = config.message_limit.max(1);
+    if config.max_bubble_width == 0 {
+        config.max_bubble_width = usize::MAX;
+    }
+    config.keymap = Keymap::new(&config.keys, config.vi_mode)
+        .map_err(|e| eyre!("{}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+// $XDG_CONFIG_HOME/global-chat, or ~/.config/global-chat
+pub fn config_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_CONFIG_HOME", ".config")
+}
+
+// $XDG_STATE_HOME/global-chat, or ~/.local/state/global-chat
+pub fn state_dir() -> Option<PathBuf> {
+    xdg_dir("XDG_STATE_HOME", ".local/state")
+}
+
+fn xdg_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))?;
+    Some(base.join("global-chat"))
+}
@@ -0,0 +1,607 @@
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// How many killed strings Ctrl-Y/Alt-Y can cycle through
+const KILL_RING_SIZE: usize = 16;
+// How many undo steps we keep
+const UNDO_LIMIT: usize = 100;
+
+// What a key did to the editor
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    // The text changed
+    Edited,
+    // Only the cursor moved
+    Moved,
+    // Not an editing key
+    Ignored,
+}
+
+// What the previous command was, so consecutive kills, yanks and typing can be grouped
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LastCommand {
+    Other,
+    Insert,
+    Kill,
+    // Yanked text now sits between these byte offsets
+    Yank { start: usize, end: usize },
+    // Moved between lines, aiming for this display column
+    Vertical { column: usize },
+}
+
+#[derive(Clone)]
+struct Snapshot {
+    text: String,
+    cursor: usize,
+}
+
+// A multi-line editor with readline/emacs bindings, a kill ring and undo/redo.
+// Lines are separated by '\n'; line-wise commands like Ctrl-A and Ctrl-K work on
+// the line the cursor is on.
+// The cursor is a byte offset into the text that always sits on a grapheme cluster
+// boundary, so an emoji ZWJ sequence or a letter with combining marks moves and
+// deletes as one unit.
+pub struct LineEditor {
+    text: String,
+    cursor: usize,
+    kill_ring: Vec<String>,
+    // Ring entry the last yank inserted, for Alt-Y to step back from
+    yank_index: usize,
+    undo_stack: Vec<Snapshot>,
+    redo_stack: Vec<Snapshot>,
+    last_command: LastCommand,
+}
+
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self {
+            text: String::new(),
+            cursor: 0,
+            kill_ring: Vec::new(),
+            yank_index: 0,
+            undo_stack: Vec::new(),
+            redo_stack: Vec::new(),
+            last_command: LastCommand::Other,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    // Line and display column of the cursor
+    pub fn cursor_position(&self) -> (usize, usize) {
+        self.position_of(self.cursor)
+    }
+
+    // Line and display column of the byte offset `index`
+    pub fn position_of(&self, index: usize) -> (usize, usize) {
+        let before = &self.text[..index];
+        let line = before.matches('\n').count();
+        (line, before[self.line_start(index)..].width())
+    }
+
+    // Characters as the user sees them, so an emoji sequence counts once
+    pub fn grapheme_count(&self) -> usize {
+        self.text.graphemes(true).count()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text.matches('\n').count() + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    // Empties the editor and hands back what was in it; undo can still bring it back
+    pub fn take(&mut self) -> String {
+        self.save_undo();
+        self.cursor = 0;
+        self.last_command = LastCommand::Other;
+        std::mem::take(&mut self.text)
+    }
+
+    // Swaps in new text with the cursor at byte offset `cursor`; undo brings the old text back
+    pub fn set_text(&mut self, text: &str, cursor: usize) {
+        self.save_undo();
+        self.text = text.to_string();
+        self.cursor = cursor.min(self.text.len());
+        self.last_command = LastCommand::Other;
+    }
+
+    // Replaces the bytes in `start..end` and leaves the cursor after the replacement
+    pub fn replace_range(&mut self, start: usize, end: usize, replacement: &str) {
+        self.save_undo();
+        self.text.replace_range(start..end, replacement);
+        self.cursor = start + replacement.len();
+        self.last_command = LastCommand::Other;
+    }
+
+    // Applies the default readline bindings for `key`
+    pub fn handle_key(&mut self, key: KeyEvent) -> Outcome {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT);
+        let before = (self.text.clone(), self.cursor);
+
+        match key.code {
+            KeyCode::Char('a') if ctrl => self.move_home(),
+            KeyCode::Char('e') if ctrl => self.move_end(),
+            KeyCode::Char('b') if ctrl => self.move_left(),
+            KeyCode::Char('f') if ctrl => self.move_right(),
+            KeyCode::Char('b') if alt => self.move_word_left(),
+            KeyCode::Char('f') if alt => self.move_word_right(),
+            KeyCode::Char('h') if ctrl => self.delete_before(),
+            KeyCode::Char('d') if ctrl => self.delete_after(),
+            KeyCode::Char('w') if ctrl => self.kill_word_before(),
+            KeyCode::Char('d') if alt => self.kill_word_after(),
+            KeyCode::Char('u') if ctrl => self.kill_to_start(),
+            KeyCode::Char('k') if ctrl => self.kill_to_end(),
+            KeyCode::Char('y') if ctrl => self.yank(),
+            KeyCode::Char('y') if alt => self.yank_pop(),
+            KeyCode::Char('j') if ctrl => self.insert_newline(),
+            // Kitty-protocol terminals report Ctrl-Shift-Z as a lowercase z with Shift held
+            KeyCode::Char('z') | KeyCode::Char('Z') if ctrl && shift => self.redo(),
+            KeyCode::Char('z') | KeyCode::Char('_') | KeyCode::Char('7') if ctrl => self.undo(),
+            KeyCode::Char('Z') if ctrl => self.redo(),
+            KeyCode::Char('z') if alt => self.redo(),
+            KeyCode::Backspace if alt || ctrl => self.kill_word_before(),
+            KeyCode::Left if alt || ctrl => self.move_word_left(),
+            KeyCode::Right if alt || ctrl => self.move_word_right(),
+            KeyCode::Enter if shift || alt => self.insert_newline(),
+            KeyCode::Up => self.move_up(),
+            KeyCode::Down => self.move_down(),
+            KeyCode::Backspace => self.delete_before(),
+            KeyCode::Delete => self.delete_after(),
+            KeyCode::Left => self.move_left(),
+            KeyCode::Right => self.move_right(),
+            KeyCode::Home => self.move_home(),
+            KeyCode::End => self.move_end(),
+            KeyCode::Char(c) if !ctrl && !alt => self.insert_char(c),
+            _ => return Outcome::Ignored,
+        }
+
+        if self.text != before.0 {
+            Outcome::Edited
+        } else if self.cursor != before.1 {
+            Outcome::Moved
+        } else {
+            Outcome::Ignored
+        }
+    }
+
+    pub fn insert_char(&mut self, c: char) {
+        // Typing runs undo as one step, split at word boundaries
+        if self.last_command != LastCommand::Insert || c.is_whitespace() {
+            self.save_undo();
+        }
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        self.last_command = LastCommand::Insert;
+    }
+
+    // Inserts a whole string as one undo step, e.g. a paste
+    pub fn insert_str(&mut self, s: &str) {
+        self.save_undo();
+        self.insert_raw(s);
+        self.last_command = LastCommand::Other;
+    }
+
+    // Removes bytes `start..end` as one undo step and hands them back
+    pub fn delete_range(&mut self, start: usize, end: usize) -> String {
+        self.save_undo();
+        let removed = self.text[start..end].to_string();
+        self.remove(start, end);
+        self.last_command = LastCommand::Other;
+        removed
+    }
+
+    pub fn insert_newline(&mut self) {
+        self.save_undo();
+        self.insert_raw("\n");
+        self.last_command = LastCommand::Other;
+    }
+
+    pub fn delete_before(&mut self) {
+        if self.cursor > 0 {
+            self.save_undo();
+            self.remove(self.previous_boundary(self.cursor), self.cursor);
+        }
+        self.last_command = LastCommand::Other;
+    }
+
+    pub fn delete_after(&mut self) {
+        if self.cursor < self.text.len() {
+            self.save_undo();
+            self.remove(self.cursor, self.next_boundary(self.cursor));
+        }
+        self.last_command = LastCommand::Other;
+    }
+
+    pub fn move_left(&mut self) {
+        self.move_to(self.previous_boundary(self.cursor));
+    }
+
+    pub fn move_right(&mut self) {
+        self.move_to(self.next_boundary(self.cursor));
+    }
+
+    // Up a line, keeping to the column we started from; does nothing on the first line
+    pub fn move_up(&mut self) {
+        let start = self.line_start(self.cursor);
+        if start > 0 {
+            let column = self.goal_column();
+            self.move_to_column(self.line_start(start - 1), column);
+        }
+    }
+
+    // Down a line; does nothing on the last line
+    pub fn move_down(&mut self) {
+        let end = self.line_end(self.cursor);
+        if end < self.text.len() {
+            let column = self.goal_column();
+            self.move_to_column(end + 1, column);
+        }
+    }
+
+    pub fn move_home(&mut self) {
+        self.move_to(self.line_start(self.cursor));
+    }
+
+    pub fn move_end(&mut self) {
+        self.move_to(self.line_end(self.cursor));
+    }
+
+    // Alt-B: back to the start of the previous word
+    pub fn move_word_left(&mut self) {
+        self.move_to(self.word_start_before(self.cursor, char::is_alphanumeric));
+    }
+
+    // Alt-F: forward to the end of the next word
+    pub fn move_word_right(&mut self) {
+        self.move_to(self.word_end_after(self.cursor));
+    }
+
+    // Ctrl-W: kill back to the previous whitespace, like unix-word-rubout
+    pub fn kill_word_before(&mut self) {
+        let start = self.word_start_before(self.cursor, |c| !c.is_whitespace());
+        self.kill(start, self.cursor, true);
+    }
+
+    // Alt-D: kill forward to the end of the next word
+    pub fn kill_word_after(&mut self) {
+        let end = self.word_end_after(self.cursor);
+        self.kill(self.cursor, end, false);
+    }
+
+    // Ctrl-U: kill back to the start of the line, or the line break when already there
+    pub fn kill_to_start(&mut self) {
+        let start = match self.line_start(self.cursor) {
+            start if start == self.cursor => start.saturating_sub(1),
+            start => start,
+        };
+        self.kill(start, self.cursor, true);
+    }
+
+    // Ctrl-K: kill to the end of the line, or the line break when already there
+    pub fn kill_to_end(&mut self) {
+        let end = match self.line_end(self.cursor) {
+            end if end == self.cursor => (end + 1).min(self.text.len()),
+            end => end,
+        };
+        self.kill(self.cursor, end, false);
+    }
+
+    // Ctrl-Y: insert the most recent kill
+    pub fn yank(&mut self) {
+        let Some(killed) = self.kill_ring.last().cloned() else {
+            return;
+        };
+        self.save_undo();
+        let start = self.cursor;
+        self.insert_raw(&killed);
+        self.yank_index = self.kill_ring.len() - 1;
+        self.last_command = LastCommand::Yank {
+            start,
+            end: self.cursor,
+        };
+    }
+
+    // Alt-Y right after a yank: swap the yanked text for the next older kill
+    pub fn yank_pop(&mut self) {
+        let LastCommand::Yank { start, end } = self.last_command else {
+            return;
+        };
+        if self.kill_ring.len() < 2 {
+            return;
+        }
+        self.yank_index = self
+            .yank_index
+            .checked_sub(1)
+            .unwrap_or(self.kill_ring.len() - 1);
+        let killed = self.kill_ring[self.yank_index].clone();
+        self.remove(start, end);
+        self.insert_raw(&killed);
+        self.last_command = LastCommand::Yank {
+            start,
+            end: self.cursor,
+        };
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(snapshot) = self.undo_stack.pop() {
+            let current = self.snapshot();
+            self.redo_stack.push(current);
+            self.restore(snapshot);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(snapshot) = self.redo_stack.pop() {
+            let current = self.snapshot();
+            self.undo_stack.push(current);
+            self.restore(snapshot);
+        }
+    }
+
+    fn kill(&mut self, start: usize, end: usize, backwards: bool) {
+        if start == end {
+            self.last_command = LastCommand::Other;
+            return;
+        }
+        self.save_undo();
+        let killed = self.text[start..end].to_string();
+        // Back-to-back kills build up one ring entry, in reading order
+        match self.kill_ring.last_mut() {
+            Some(last) if self.last_command == LastCommand::Kill => {
+                if backwards {
+                    last.insert_str(0, &killed);
+                } else {
+                    last.push_str(&killed);
+                }
+            }
+            _ => {
+                self.kill_ring.push(killed);
+                if self.kill_ring.len() > KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+            }
+        }
+        self.remove(start, end);
+        self.last_command = LastCommand::Kill;
+    }
+
+    pub fn move_to(&mut self, cursor: usize) {
+        self.cursor = cursor.min(self.text.len());
+        self.last_command = LastCommand::Other;
+    }
+
+    // Puts the cursor at display `column` of `line`, or as close as the text allows,
+    // as when the input box is clicked
+    pub fn move_to_point(&mut self, line: usize, column: usize) {
+        let start = self
+            .text
+            .split('\n')
+            .take(line.min(self.line_count() - 1))
+            .map(|line| line.len() + 1)
+            .sum();
+        self.move_to_column(start, column);
+        self.last_command = LastCommand::Other;
+    }
+
+    fn goal_column(&self) -> usize {
+        match self.last_command {
+            LastCommand::Vertical { column } => column,
+            _ => self.cursor_position().1,
+        }
+    }
+
+    // Puts the cursor on the line starting at `start`, as close to `column` as it gets
+    // without splitting a wide character
+    fn move_to_column(&mut self, start: usize, column: usize) {
+        let end = self.line_end(start);
+        let mut cursor = start;
+        let mut width = 0;
+        for (index, grapheme) in self.text[start..end].grapheme_indices(true) {
+            width += grapheme.width();
+            if width > column {
+                break;
+            }
+            cursor = start + index + grapheme.len();
+        }
+        self.cursor = cursor;
+        self.last_command = LastCommand::Vertical { column };
+    }
+
+    // Byte offset where the line containing `from` starts
+    pub fn line_start(&self, from: usize) -> usize {
+        self.text[..from].rfind('\n').map_or(0, |index| index + 1)
+    }
+
+    // Byte offset of the line break (or end of text) after `from`
+    pub fn line_end(&self, from: usize) -> usize {
+        self.text[from..]
+            .find('\n')
+            .map_or(self.text.len(), |index| from + index)
+    }
+
+    // Start of the word ending at or before `from`, where words are runs of graphemes
+    // whose first character is `in_word`
+    fn word_start_before(&self, from: usize, in_word: impl Fn(char) -> bool) -> usize {
+        let mut graphemes = self.text[..from].grapheme_indices(true).rev().peekable();
+        let mut index = from;
+        while let Some((start, _)) = graphemes.next_if(|(_, g)| !starts_with(g, &in_word)) {
+            index = start;
+        }
+        while let Some((start, _)) = graphemes.next_if(|(_, g)| starts_with(g, &in_word)) {
+            index = start;
+        }
+        index
+    }
+
+    fn word_end_after(&self, from: usize) -> usize {
+        let mut graphemes = self.text[from..].graphemes(true).peekable();
+        let mut index = from;
+        while let Some(g) = graphemes.next_if(|g| !starts_with(g, char::is_alphanumeric)) {
+            index += g.len();
+        }
+        while let Some(g) = graphemes.next_if(|g| starts_with(g, char::is_alphanumeric)) {
+            index += g.len();
+        }
+        index
+    }
+
+    pub fn previous_boundary(&self, from: usize) -> usize {
+        self.text[..from]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(index, _)| index)
+    }
+
+    pub fn next_boundary(&self, from: usize) -> usize {
+        self.text[from..]
+            .graphemes(true)
+            .next()
+            .map_or(from, |g| from + g.len())
+    }
+
+    fn insert_raw(&mut self, s: &str) {
+        self.text.insert_str(self.cursor, s);
+        self.cursor += s.len();
+    }
+
+    // Removes bytes `start..end` and leaves the cursor at `start`
+    fn remove(&mut self, start: usize, end: usize) {
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            text: self.text.clone(),
+            cursor: self.cursor,
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.text = snapshot.text;
+        self.cursor = snapshot.cursor;
+        self.last_command = LastCommand::Other;
+    }
+
+    // Called before every change; a new change also forgets anything that could be redone
+    fn save_undo(&mut self) {
+        self.redo_stack.clear();
+        if self
+            .undo_stack
+            .last()
+            .is_some_and(|last| last.text == self.text)
+        {
+            return;
+        }
+        self.undo_stack.push(self.snapshot());
+        if self.undo_stack.len() > UNDO_LIMIT {
+            self.undo_stack.remove(0);
+        }
+    }
+}
+
+fn starts_with(grapheme: &str, predicate: impl Fn(char) -> bool) -> bool {
+    grapheme.chars().next().is_some_and(predicate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str) -> LineEditor {
+        let mut editor = LineEditor::new();
+        text.chars().for_each(|c| editor.insert_char(c));
+        editor
+    }
+
+    #[test]
+    fn moves_over_whole_graphemes() {
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let mut editor = typed(&format!("a{}e\u{301}", family));
+        editor.move_left();
+        assert_eq!(editor.cursor(), 1 + family.len());
+        editor.move_left();
+        assert_eq!(editor.cursor(), 1);
+        editor.move_right();
+        assert_eq!(editor.cursor(), 1 + family.len());
+    }
+
+    #[test]
+    fn backspace_deletes_a_whole_grapheme() {
+        let mut editor = typed("ok 👍🏽");
+        editor.delete_before();
+        assert_eq!(editor.text(), "ok ");
+        assert_eq!(editor.grapheme_count(), 3);
+    }
+
+    #[test]
+    fn vertical_moves_keep_the_goal_column() {
+        let mut editor = typed("abcdef\nab\nabcdef");
+        editor.move_to(5);
+        editor.move_down();
+        assert_eq!(editor.cursor_position(), (1, 2));
+        editor.move_down();
+        assert_eq!(editor.cursor_position(), (2, 5));
+    }
+
+    #[test]
+    fn vertical_moves_dont_split_wide_characters() {
+        let mut editor = typed("漢字x\nabcd");
+        editor.move_to_point(1, 3);
+        editor.move_up();
+        assert_eq!(editor.cursor_position(), (0, 2));
+    }
+
+    #[test]
+    fn typing_undoes_a_word_at_a_time() {
+        let mut editor = typed("hello world");
+        editor.undo();
+        assert_eq!(editor.text(), "hello");
+        editor.undo();
+        assert_eq!(editor.text(), "");
+        editor.redo();
+        assert_eq!(editor.text(), "hello");
+        editor.redo();
+        assert_eq!(editor.text(), "hello world");
+    }
+
+    #[test]
+    fn back_to_back_kills_yank_as_one() {
+        let mut editor = typed("one two three");
+        editor.kill_word_before();
+        editor.kill_word_before();
+        assert_eq!(editor.text(), "one ");
+        editor.yank();
+        assert_eq!(editor.text(), "one two three");
+    }
+
+    #[test]
+    fn yank_pop_cycles_older_kills() {
+        let mut editor = typed("first");
+        editor.kill_to_start();
+        "second".chars().for_each(|c| editor.insert_char(c));
+        editor.kill_to_start();
+        editor.yank();
+        assert_eq!(editor.text(), "second");
+        editor.yank_pop();
+        assert_eq!(editor.text(), "first");
+    }
+}
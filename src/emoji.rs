@@ -0,0 +1,199 @@
+use emojis::Emoji;
+
+// How many emoji the picker offers at once
+pub const PICKER_SIZE: usize = 8;
+// How many recently used emoji we remember
+const RECENT_LIMIT: usize = 16;
+// Characters typed after the `:` before the picker opens, so :D and :P stay emoticons
+const MIN_QUERY: usize = 2;
+
+// Emoji picked or sent lately, most recent first
+#[derive(Default)]
+pub struct Recent(Vec<&'static Emoji>);
+
+impl Recent {
+    pub fn record(&mut self, emoji: &'static Emoji) {
+        self.0.retain(|recent| *recent != emoji);
+        self.0.insert(0, emoji);
+        self.0.truncate(RECENT_LIMIT);
+    }
+
+    fn rank(&self, emoji: &Emoji) -> Option<usize> {
+        self.0.iter().position(|recent| *recent == emoji)
+    }
+}
+
+// Replaces every known `:shortcode:` with its emoji, leaving code spans and fences alone
+pub fn expand_shortcodes(text: &str, recent: &mut Recent) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    // Backtick run that opened the code span we're in, if any
+    let mut code_fence: Option<usize> = None;
+
+    while let Some(index) = rest.find(['`', ':']) {
+        expanded.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if rest.starts_with('`') {
+            let run = rest.len() - rest.trim_start_matches('`').len();
+            code_fence = match code_fence {
+                Some(open) if open == run => None,
+                None => Some(run),
+                open => open,
+            };
+            expanded.push_str(&rest[..run]);
+            rest = &rest[run..];
+            continue;
+        }
+
+        let shortcode = rest[1..]
+            .find(':')
+            .map(|end| &rest[1..end + 1])
+            .filter(|name| !name.is_empty() && name.chars().all(is_shortcode_char));
+        match shortcode.and_then(emojis::get_by_shortcode) {
+            Some(emoji) if code_fence.is_none() => {
+                recent.record(emoji);
+                expanded.push_str(emoji.as_str());
+                rest = &rest[shortcode.unwrap().len() + 2..];
+            }
+            _ => {
+                expanded.push(':');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+pub fn is_shortcode_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+')
+}
+
+// Best matches for a partial shortcode: recently used first, then by how well the name fits
+pub fn search(query: &str, recent: &Recent) -> Vec<&'static Emoji> {
+    let query = query.to_lowercase();
+    let mut matches: Vec<(usize, i32, &'static Emoji)> = emojis::iter()
+        .filter_map(|emoji| {
+            let score = emoji
+                .shortcodes()
+                .filter_map(|shortcode| fuzzy_score(&query, shortcode))
+                .max()?;
+            Some((recent.rank(emoji).unwrap_or(usize::MAX), score, emoji))
+        })
+        .collect();
+
+    matches.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    matches
+        .into_iter()
+        .take(PICKER_SIZE)
+        .map(|(_, _, emoji)| emoji)
+        .collect()
+}
+
+// Scores `candidate` when every character of `query` appears in it in order.
+// Prefix and whole-word hits beat scattered ones, and shorter names win ties.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    if candidate == query {
+        return Some(1000);
+    }
+    if candidate.starts_with(query) {
+        return Some(800 - candidate.len() as i32);
+    }
+    if let Some(index) = candidate.find(query) {
+        let word_start = candidate[..index].ends_with('_');
+        return Some(if word_start { 600 } else { 400 } - candidate.len() as i32);
+    }
+
+    let mut score = 0;
+    let mut last_match: Option<usize> = None;
+    let mut chars = candidate.char_indices();
+    for wanted in query.chars() {
+        let (index, _) = chars.find(|(_, c)| *c == wanted)?;
+        // Consecutive hits read like the word being typed
+        if last_match.is_some_and(|last| last + 1 == index) {
+            score += 5;
+        }
+        last_match = Some(index);
+    }
+    Some(score - candidate.len() as i32)
+}
+
+// Popup state while a `:shortcode` is being typed
+pub struct Picker {
+    // Byte offset of the `:` that opened the picker
+    pub start: usize,
+    pub matches: Vec<&'static Emoji>,
+    pub selected: usize,
+}
+
+impl Picker {
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1).min(self.matches.len().saturating_sub(1));
+    }
+
+    pub fn selected(&self) -> Option<&'static Emoji> {
+        self.matches.get(self.selected).copied()
+    }
+}
+
+// The `:query` being typed right before `cursor` (a byte offset), if any.
+// The colon has to start a word so times like 10:30 and URLs don't open the picker.
+pub fn shortcode_at(input: &str, cursor: usize) -> Option<(usize, String)> {
+    let before = &input[..cursor];
+    let query = &before[before.trim_end_matches(is_shortcode_char).len()..];
+    if query.len() < MIN_QUERY {
+        return None;
+    }
+    let preceding = before[..before.len() - query.len()].strip_suffix(':')?;
+    if preceding.ends_with(|c: char| !c.is_whitespace()) {
+        return None;
+    }
+    Some((preceding.len(), query.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at_end(input: &str) -> Option<(usize, String)> {
+        shortcode_at(input, input.len())
+    }
+
+    #[test]
+    fn emoticons_dont_open_the_picker() {
+        for input in ["lol :D", ":P", "nice :3", "ok :)", "hmm :-)"] {
+            assert_eq!(at_end(input), None, "{}", input);
+        }
+    }
+
+    #[test]
+    fn shortcodes_open_the_picker_from_two_characters() {
+        assert_eq!(at_end(":f"), None);
+        assert_eq!(at_end("so :fi"), Some((3, "fi".to_string())));
+        assert_eq!(at_end(":+1"), Some((0, "+1".to_string())));
+    }
+
+    #[test]
+    fn colons_inside_words_dont_open_the_picker() {
+        assert_eq!(at_end("at 10:30"), None);
+        assert_eq!(at_end("https://example"), None);
+    }
+
+    #[test]
+    fn expands_known_shortcodes_outside_code() {
+        let mut recent = Recent::default();
+        assert_eq!(expand_shortcodes("hot :fire: :D", &mut recent), "hot 🔥 :D");
+        assert_eq!(
+            expand_shortcodes("`:fire:` :nope:", &mut recent),
+            "`:fire:` :nope:"
+        );
+    }
+}
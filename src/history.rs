@@ -0,0 +1,217 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+// How many sent messages we keep around
+const HISTORY_LIMIT: usize = 1000;
+
+// Messages we've sent, oldest first, saved one JSON string per line so multi-line
+// messages survive the round trip
+pub struct History {
+    entries: Vec<String>,
+    path: Option<PathBuf>,
+    // Entry currently recalled into the input with Up/Down
+    recalled: Option<usize>,
+}
+
+// An in-progress Ctrl-R search
+pub struct Search {
+    pub query: String,
+    // Input from before the search, put back if it's cancelled
+    pub original: String,
+    // Entry index and byte offset of the current match
+    pub found: Option<(usize, usize)>,
+}
+
+impl History {
+    // Reads saved history from `path`; a missing or unreadable file just means no history
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut entries: Vec<String> = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+
+        // The file only ever gets appended to, so trim it back down now and then
+        if entries.len() > HISTORY_LIMIT {
+            entries.drain(..entries.len() - HISTORY_LIMIT);
+            if let Some(path) = &path {
+                let _ = fs::write(path, serialize(&entries));
+            }
+        }
+
+        Self {
+            entries,
+            path,
+            recalled: None,
+        }
+    }
+
+    // Remembers a sent message, skipping repeats of the last one
+    pub fn push(&mut self, entry: &str) -> io::Result<()> {
+        self.recalled = None;
+        if entry.trim().is_empty() || self.entries.last().is_some_and(|last| last == entry) {
+            return Ok(());
+        }
+        self.entries.push(entry.to_string());
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(serialize(&self.entries[self.entries.len() - 1..]).as_bytes())
+    }
+
+    // The entry before the recalled one, as long as the input is empty or still
+    // holds exactly what we recalled
+    pub fn previous(&mut self, current: &str) -> Option<&str> {
+        let from = match self.recalled {
+            Some(index) if self.entries[index] == current => index,
+            _ if current.is_empty() => self.entries.len(),
+            _ => return None,
+        };
+        let index = from.checked_sub(1)?;
+        self.recalled = Some(index);
+        Some(&self.entries[index])
+    }
+
+    // The entry after the recalled one, or an empty input once we walk past the newest
+    pub fn next(&mut self, current: &str) -> Option<&str> {
+        let index = self
+            .recalled
+            .filter(|&index| self.entries[index] == current)?;
+        if index + 1 < self.entries.len() {
+            self.recalled = Some(index + 1);
+            Some(&self.entries[index + 1])
+        } else {
+            self.recalled = None;
+            Some("")
+        }
+    }
+
+    pub fn entry(&self, index: usize) -> &str {
+        &self.entries[index]
+    }
+
+    // Newest entry older than `before` containing `query`, with where the match starts
+    pub fn search(&self, query: &str, before: usize) -> Option<(usize, usize)> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, entry)| entry.find(query).map(|offset| (index, offset)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+fn serialize(entries: &[String]) -> String {
+    entries
+        .iter()
+        .filter_map(|entry| serde_json::to_string(entry).ok())
+        .map(|line| line + "\n")
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A history file of its own for each test, removed again on drop
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "global-chat-history-{}-{}.jsonl",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_file(&path);
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn history(entries: &[&str]) -> History {
+        let mut history = History::load(None);
+        for entry in entries {
+            history.push(entry).unwrap();
+        }
+        history
+    }
+
+    #[test]
+    fn skips_repeats_and_blank_entries() {
+        let history = history(&["hi", "hi", "  ", "yo", "hi"]);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.entry(2), "hi");
+    }
+
+    #[test]
+    fn recalls_back_and_forth() {
+        let mut history = history(&["one", "two"]);
+        assert_eq!(history.previous(""), Some("two"));
+        assert_eq!(history.previous("two"), Some("one"));
+        assert_eq!(history.previous("one"), None);
+        assert_eq!(history.next("one"), Some("two"));
+        assert_eq!(history.next("two"), Some(""));
+        assert_eq!(history.next(""), None);
+    }
+
+    #[test]
+    fn leaves_an_edited_input_alone() {
+        let mut history = history(&["one", "two"]);
+        assert_eq!(history.previous("draft"), None);
+        history.previous("");
+        assert_eq!(history.previous("two, edited"), None);
+    }
+
+    #[test]
+    fn searches_older_entries_newest_first() {
+        let history = history(&["cargo build", "git push", "cargo test"]);
+        assert_eq!(history.search("cargo", 3), Some((2, 0)));
+        assert_eq!(history.search("cargo", 2), Some((0, 0)));
+        assert_eq!(history.search("push", 1), None);
+    }
+
+    #[test]
+    fn persists_multi_line_entries() {
+        let file = TempFile::new("persist");
+        let mut history = History::load(Some(file.0.clone()));
+        history.push("line one\nline two").unwrap();
+        history.push("\"quoted\"").unwrap();
+
+        let history = History::load(Some(file.0.clone()));
+        assert_eq!(history.len(), 2);
+        assert_eq!(history.entry(0), "line one\nline two");
+        assert_eq!(history.entry(1), "\"quoted\"");
+    }
+
+    #[test]
+    fn trims_the_file_to_the_limit() {
+        let file = TempFile::new("trim");
+        let entries: Vec<String> = (0..HISTORY_LIMIT + 5).map(|i| i.to_string()).collect();
+        fs::write(&file.0, serialize(&entries)).unwrap();
+
+        let history = History::load(Some(file.0.clone()));
+        assert_eq!(history.len(), HISTORY_LIMIT);
+        assert_eq!(history.entry(0), "5");
+        assert_eq!(
+            fs::read_to_string(&file.0).unwrap().lines().count(),
+            HISTORY_LIMIT
+        );
+    }
+}
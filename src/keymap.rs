@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+
+use crate::vi;
+
+// Something a key can be bound to in the app's own modes; vi keys, the readline
+// editing keys and popups have fixed bindings
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Quit,
+    Help,
+    // Normal mode
+    Insert,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    Latest,
+    Select,
+    Sidebar,
+    FocusSidebar,
+    CopyCode,
+    CopyScreen,
+    Links,
+    Mouse,
+    Layout,
+    // Editing
+    Send,
+    Leave,
+    SearchHistory,
+}
+
+impl Action {
+    // The name used in config.toml
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Help => "help",
+            Action::Insert => "insert",
+            Action::ScrollUp => "scroll-up",
+            Action::ScrollDown => "scroll-down",
+            Action::PageUp => "page-up",
+            Action::PageDown => "page-down",
+            Action::Latest => "latest",
+            Action::Select => "select",
+            Action::Sidebar => "sidebar",
+            Action::FocusSidebar => "focus-sidebar",
+            Action::CopyCode => "copy-code",
+            Action::CopyScreen => "copy-screen",
+            Action::Links => "links",
+            Action::Mouse => "mouse",
+            Action::Layout => "layout",
+            Action::Send => "send",
+            Action::Leave => "leave",
+            Action::SearchHistory => "search-history",
+        }
+    }
+
+    pub fn about(self) -> &'static str {
+        match self {
+            Action::Quit => "rage quit",
+            Action::Help => "show these keys",
+            Action::Insert => "start typing",
+            Action::ScrollUp => "scroll up a line",
+            Action::ScrollDown => "scroll down a line",
+            Action::PageUp => "scroll up a page",
+            Action::PageDown => "scroll down a page",
+            Action::Latest => "jump to the latest message",
+            Action::Select => "pick a message to act on",
+            Action::Sidebar => "toggle who's online",
+            Action::FocusSidebar => "move into the sidebar",
+            Action::CopyCode => "copy the latest code block",
+            Action::CopyScreen => "copy the messages on screen",
+            Action::Links => "pick a link to open",
+            Action::Mouse => "toggle mouse capture",
+            Action::Layout => "toggle the compact layout",
+            Action::Send => "send the message",
+            Action::Leave => "stop typing",
+            Action::SearchHistory => "search what you sent",
+        }
+    }
+}
+
+// The modes with rebindable keys
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Mode {
+    Normal,
+    Editing,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Normal => "normal",
+            Mode::Editing => "editing",
+        }
+    }
+}
+
+// Built-in bindings, in the order the help overlay lists them
+const DEFAULTS: &[(Mode, Action, &[&str])] = &[
+    (Mode::Normal, Action::Insert, &["enter"]),
+    (Mode::Normal, Action::ScrollUp, &["up", "k"]),
+    (Mode::Normal, Action::ScrollDown, &["down", "j"]),
+    (Mode::Normal, Action::PageUp, &["pageup"]),
+    (Mode::Normal, Action::PageDown, &["pagedown"]),
+    (Mode::Normal, Action::Latest, &["end", "G"]),
+    (Mode::Normal, Action::Select, &["s"]),
+    (Mode::Normal, Action::Sidebar, &["u"]),
+    (Mode::Normal, Action::FocusSidebar, &["tab"]),
+    (Mode::Normal, Action::CopyCode, &["c"]),
+    (Mode::Normal, Action::CopyScreen, &["y"]),
+    (Mode::Normal, Action::Links, &["o"]),
+    (Mode::Normal, Action::Mouse, &["m"]),
+    (Mode::Normal, Action::Layout, &["l"]),
+    (Mode::Normal, Action::Help, &["?", "f1"]),
+    (Mode::Normal, Action::Quit, &["q"]),
+    (Mode::Editing, Action::Send, &["enter"]),
+    (Mode::Editing, Action::Leave, &["esc"]),
+    (Mode::Editing, Action::SearchHistory, &["ctrl-r"]),
+    (Mode::Editing, Action::PageUp, &["pageup"]),
+    (Mode::Editing, Action::PageDown, &["pagedown"]),
+    (Mode::Editing, Action::Latest, &["ctrl-end"]),
+    (Mode::Editing, Action::Help, &["f1"]),
+    (Mode::Editing, Action::Quit, &["ctrl-q"]),
+];
+
+// Keys the input box handles before the keymap gets a look, and what they're for
+const EDITING_RESERVED: &[(&[&str], &str)] = &[
+    (&["tab", "backtab"], "completion"),
+    (&["ctrl-x"], "Ctrl-X Ctrl-E"),
+    (&["up", "down", "ctrl-p", "ctrl-n"], "history"),
+    (
+        &[
+            "ctrl-a",
+            "ctrl-e",
+            "ctrl-b",
+            "ctrl-f",
+            "alt-b",
+            "alt-f",
+            "ctrl-h",
+            "ctrl-d",
+            "ctrl-w",
+            "alt-d",
+            "ctrl-u",
+            "ctrl-k",
+            "ctrl-y",
+            "alt-y",
+            "ctrl-j",
+            "ctrl-z",
+            "ctrl-Z",
+            "ctrl-_",
+            "ctrl-7",
+            "alt-z",
+            "shift-enter",
+            "alt-enter",
+            "backspace",
+            "ctrl-backspace",
+            "alt-backspace",
+            "delete",
+            "left",
+            "right",
+            "ctrl-left",
+            "ctrl-right",
+            "alt-left",
+            "alt-right",
+            "home",
+            "end",
+        ],
+        "line editing",
+    ),
+];
+
+// Keys that can't be rebound, listed next to the keymap in the help overlay
+pub const SIDEBAR_KEYS: &[(&str, &str)] = &[
+    ("Up/k Down/j", "pick someone"),
+    ("Enter", "open their whois card"),
+    ("Tab/Esc", "back to the messages"),
+];
+
+pub const VI_KEYS: &[(&str, &str)] = &[
+    ("i a I A o O", "insert"),
+    ("h l w b e 0 ^ $", "move"),
+    ("d c y + motion", "delete, change, yank"),
+    ("dd cc yy", "whole lines"),
+    ("x p P u Ctrl-R", "delete, put, undo, redo"),
+    ("v", "visual selection"),
+    ("j k gg G", "scroll the messages"),
+    ("Ctrl-D Ctrl-U", "scroll half a page"),
+    ("Enter", "send the message"),
+];
+
+pub const EDITING_KEYS: &[(&str, &str)] = &[
+    ("Shift/Alt-Enter Ctrl-J", "new line"),
+    ("Up/Down Ctrl-P/N", "sent history, or move between lines"),
+    ("Tab Shift-Tab", "complete @users, /commands and :emoji:"),
+    ("Ctrl-A/E Alt-B/F", "line start/end, word back/forward"),
+    ("Ctrl-W/U/K Ctrl-Y", "kill and yank"),
+    ("Ctrl-Z Alt-Z", "undo, redo"),
+    ("Ctrl-X Ctrl-E", "open $EDITOR"),
+];
+
+pub const SELECTING_KEYS: &[(&str, &str)] = &[
+    ("Up/k Down/j", "move between messages"),
+    ("g/Home G/End", "first, last"),
+    ("Enter/Space", "actions menu"),
+    ("c", "copy its code block"),
+    ("Esc/q", "stop selecting"),
+];
+
+// One key, as written in config.toml: "q", "G", "ctrl-r", "alt-enter", "f1"
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = text;
+        // A lone "-" is the minus key, not an empty modifier
+        while let Some((modifier, after)) =
+            rest.split_once('-').filter(|(_, after)| !after.is_empty())
+        {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return None,
+            };
+            rest = after;
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return None,
+                },
+            },
+        };
+        Some(Self::new(code, modifiers))
+    }
+
+    // Shift is part of the character itself, so "shift-g" and "G" are the same key
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => Self {
+                code: KeyCode::Char(c.to_ascii_uppercase()),
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => Self {
+                code: KeyCode::BackTab,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            KeyCode::BackTab => Self {
+                code,
+                modifiers: modifiers - KeyModifiers::SHIFT,
+            },
+            _ => Self { code, modifiers },
+        }
+    }
+
+    // A plain character would be typed into the input instead
+    fn is_printable(self) -> bool {
+        matches!(self.code, KeyCode::Char(_))
+            && !self
+                .modifiers
+                .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+    }
+
+    // What gets this key before the keymap in `mode`, if anything
+    fn reserved(self, mode: Mode, vi_mode: bool) -> Option<(Self, &'static str)> {
+        let owner = match mode {
+            Mode::Normal if vi_mode && vi::takes(KeyEvent::new(self.code, self.modifiers)) => {
+                "vi mode"
+            }
+            Mode::Normal => return None,
+            Mode::Editing => EDITING_RESERVED.iter().find_map(|(keys, owner)| {
+                keys.iter()
+                    .any(|key| Key::parse(key) == Some(self))
+                    .then_some(*owner)
+            })?,
+        };
+        Some((self, owner))
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(key: KeyEvent) -> Self {
+        Self::new(key.code, key.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            // Ctrl-R rather than Ctrl-r, the way shells and editors write it
+            KeyCode::Char(c) if !self.modifiers.is_empty() => {
+                write!(f, "{}", c.to_ascii_uppercase())
+            }
+            KeyCode::Char(c) => write!(f, "{}", c),
+            KeyCode::BackTab => write!(f, "Shift-Tab"),
+            KeyCode::F(n) => write!(f, "F{}", n),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            code => write!(f, "{:?}", code),
+        }
+    }
+}
+
+// One key or a list of them, so `quit = "q"` and `quit = ["q", "ctrl-c"]` both work
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Keys {
+    fn iter(&self) -> impl Iterator<Item = &str> {
+        let keys = match self {
+            Keys::One(key) => std::slice::from_ref(key),
+            Keys::Many(keys) => keys.as_slice(),
+        };
+        keys.iter().map(String::as_str)
+    }
+}
+
+// The [keys.normal] and [keys.editing] tables; each action listed there loses its
+// default keys for the ones given
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct KeyConfig {
+    pub normal: HashMap<Action, Keys>,
+    pub editing: HashMap<Action, Keys>,
+}
+
+#[derive(Debug)]
+pub struct Keymap {
+    // Per mode, in DEFAULTS order, for lookup and for listing
+    bindings: HashMap<Mode, Vec<(Action, Vec<Key>)>>,
+}
+
+impl Keymap {
+    // Merges the user's keys over the defaults, refusing keys that don't parse, actions
+    // that don't belong in a mode, one key doing two things, editing-mode keys that would
+    // keep a character from being typed, and keys something else gets to first
+    pub fn new(config: &KeyConfig, vi_mode: bool) -> Result<Self, String> {
+        let mut bindings: HashMap<Mode, Vec<(Action, Vec<Key>)>> = HashMap::new();
+        for (mode, action, keys) in DEFAULTS {
+            let keys = keys.iter().map(|key| Key::parse(key).unwrap()).collect();
+            bindings.entry(*mode).or_default().push((*action, keys));
+        }
+
+        for (mode, overrides) in [
+            (Mode::Normal, &config.normal),
+            (Mode::Editing, &config.editing),
+        ] {
+            let actions = bindings.get_mut(&mode).unwrap();
+            // Sorted so the first error reported doesn't depend on hash order
+            let mut overrides: Vec<_> = overrides.iter().collect();
+            overrides.sort_by_key(|(action, _)| action.name());
+            for (action, keys) in overrides {
+                let Some((_, bound)) = actions.iter_mut().find(|(a, _)| a == action) else {
+                    return Err(format!(
+                        "keys.{}: {} isn't available in {} mode",
+                        mode.name(),
+                        action.name(),
+                        mode.name()
+                    ));
+                };
+                *bound = keys
+                    .iter()
+                    .map(|text| {
+                        Key::parse(text).ok_or_else(|| {
+                            format!(
+                                "keys.{}.{}: don't know the key \"{}\"",
+                                mode.name(),
+                                action.name(),
+                                text
+                            )
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                if let Some((key, owner)) = bound.iter().find_map(|key| key.reserved(mode, vi_mode))
+                {
+                    return Err(format!(
+                        "keys.{}.{}: {} is taken by {}",
+                        mode.name(),
+                        action.name(),
+                        key,
+                        owner
+                    ));
+                }
+            }
+
+            let mut seen: HashMap<Key, Action> = HashMap::new();
+            for (action, keys) in actions.iter() {
+                for key in keys {
+                    if mode == Mode::Editing && key.is_printable() {
+                        return Err(format!(
+                            "keys.editing.{}: {} would stop you typing it; add ctrl- or alt-",
+                            action.name(),
+                            key
+                        ));
+                    }
+                    if let Some(other) = seen.insert(*key, *action)
+                        && other != *action
+                    {
+                        return Err(format!(
+                            "keys.{}: {} is bound to both {} and {}",
+                            mode.name(),
+                            key,
+                            other.name(),
+                            action.name()
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(Self { bindings })
+    }
+
+    pub fn action(&self, mode: Mode, key: KeyEvent) -> Option<Action> {
+        let key = Key::from(key);
+        self.bindings[&mode]
+            .iter()
+            .find(|(_, keys)| keys.contains(&key))
+            .map(|(action, _)| *action)
+    }
+
+    // Every binding of `mode`, for the help overlay
+    pub fn bindings(&self, mode: Mode) -> &[(Action, Vec<Key>)] {
+        &self.bindings[&mode]
+    }
+
+    // The first key bound to `action`, for the help line
+    pub fn key(&self, mode: Mode, action: Action) -> String {
+        self.bindings[&mode]
+            .iter()
+            .find(|(a, _)| *a == action)
+            .and_then(|(_, keys)| keys.first())
+            .map_or_else(|| String::from("(unbound)"), Key::to_string)
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&KeyConfig::default(), false).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(toml: &str, vi_mode: bool) -> Result<Keymap, String> {
+        Keymap::new(&toml::from_str(toml).unwrap(), vi_mode)
+    }
+
+    fn error(toml: &str, vi_mode: bool) -> String {
+        keymap(toml, vi_mode).unwrap_err()
+    }
+
+    #[test]
+    fn parses_and_prints_keys() {
+        for (text, shown) in [
+            ("q", "q"),
+            ("shift-g", "G"),
+            ("ctrl-r", "Ctrl-R"),
+            ("alt-enter", "Alt-Enter"),
+            ("shift-tab", "Shift-Tab"),
+            ("pageup", "PgUp"),
+            ("f12", "F12"),
+            ("-", "-"),
+        ] {
+            assert_eq!(Key::parse(text).unwrap().to_string(), shown, "{}", text);
+        }
+        for text in ["", "hyper-q", "f13", "enterr"] {
+            assert_eq!(Key::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn overrides_replace_the_defaults() {
+        let keymap = keymap("[normal]\nquit = [\"x\", \"ctrl-c\"]", false).unwrap();
+        let press = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        assert_eq!(keymap.action(Mode::Normal, press('x')), Some(Action::Quit));
+        assert_eq!(keymap.action(Mode::Normal, press('q')), None);
+        assert_eq!(keymap.key(Mode::Normal, Action::Quit), "x");
+    }
+
+    #[test]
+    fn rejects_keys_it_cant_parse() {
+        assert_eq!(
+            error("[normal]\nquit = \"bogus-key\"", false),
+            "keys.normal.quit: don't know the key \"bogus-key\""
+        );
+    }
+
+    #[test]
+    fn rejects_actions_from_the_other_mode() {
+        assert_eq!(
+            error("[editing]\nselect = \"ctrl-s\"", false),
+            "keys.editing: select isn't available in editing mode"
+        );
+        assert!(keymap("[normal]\nsend = \"x\"", false).is_err());
+    }
+
+    #[test]
+    fn rejects_one_key_for_two_actions() {
+        assert_eq!(
+            error("[normal]\nquit = \"k\"", false),
+            "keys.normal: k is bound to both scroll-up and quit"
+        );
+    }
+
+    #[test]
+    fn rejects_printable_keys_while_editing() {
+        assert!(error("[editing]\nsend = \"x\"", false).contains("would stop you typing it"));
+    }
+
+    #[test]
+    fn rejects_keys_the_input_box_takes_first() {
+        assert_eq!(
+            error("[editing]\nsend = \"tab\"", false),
+            "keys.editing.send: Tab is taken by completion"
+        );
+        assert_eq!(
+            error("[editing]\nleave = \"ctrl-a\"", false),
+            "keys.editing.leave: Ctrl-A is taken by line editing"
+        );
+        assert!(keymap("[editing]\nleave = [\"esc\", \"ctrl-c\"]", false).is_ok());
+    }
+
+    #[test]
+    fn rejects_vi_keys_only_in_vi_mode() {
+        assert!(keymap("[normal]\nselect = \"v\"", false).is_ok());
+        assert_eq!(
+            error("[normal]\nselect = \"v\"", true),
+            "keys.normal.select: v is taken by vi mode"
+        );
+        assert!(keymap("[normal]\nselect = \"S\"", true).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::process::{Command, Stdio};
+
+use ratatui::{
+    backend::CrosstermBackend,
+    buffer::{Buffer, Cell},
+    crossterm::{
+        cursor::{MoveTo, RestorePosition, SavePosition},
+        queue,
+        style::{Attribute, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    },
+    style::Modifier,
+};
+use unicode_width::UnicodeWidthStr;
+
+// A run of on-screen cells that should open `url` when clicked
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LinkRegion {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub url: String,
+}
+
+// Byte ranges of bare http(s) and www. URLs in `text`
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut offset = 0;
+
+    while let Some(start) = next_url_start(&text[offset..]).map(|start| start + offset) {
+        let rest = &text[start..];
+        let len = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '`'))
+            .unwrap_or(rest.len());
+        let end = start + trim_url_end(&rest[..len]);
+        if end > start {
+            urls.push(start..end);
+        }
+        offset = start + len.max(1);
+    }
+    urls
+}
+
+fn next_url_start(text: &str) -> Option<usize> {
+    ["https://", "http://", "www."]
+        .iter()
+        .filter_map(|scheme| {
+            text.match_indices(scheme)
+                .map(|(index, _)| index)
+                // "www." glued onto a word is not a URL
+                .find(|&index| {
+                    index == 0 || !text[..index].ends_with(|c: char| c.is_alphanumeric())
+                })
+        })
+        .min()
+}
+
+// Drops trailing punctuation that belongs to the sentence rather than the URL
+fn trim_url_end(url: &str) -> usize {
+    let mut end = url.len();
+    loop {
+        let trimmed = &url[..end];
+        match trimmed.chars().last() {
+            Some('.' | ',' | ';' | ':' | '!' | '?' | '\'' | '*' | '_' | '~') => end -= 1,
+            // Keep a closing paren when it balances one inside the URL (wikipedia style)
+            Some(')') if trimmed.matches('(').count() < trimmed.matches(')').count() => end -= 1,
+            _ => return end,
+        }
+    }
+}
+
+// The URL to hand to a browser: "www." links need a scheme
+pub fn normalize(url: &str) -> String {
+    if url.starts_with("www.") {
+        format!("https://{}", url)
+    } else {
+        url.to_string()
+    }
+}
+
+// Opens `url` in the desktop's default handler without waiting for it
+pub fn open(url: &str) -> io::Result<()> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    Command::new(opener)
+        .arg(normalize(url))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+// What's drawn under each region. Ratatui re-prints changed cells as plain text, dropping
+// the hyperlink, so a change here means the links need writing again.
+pub fn cells_under(buffer: &Buffer, regions: &[LinkRegion]) -> Vec<Cell> {
+    regions
+        .iter()
+        .flat_map(|region| {
+            (region.x..region.x + region.width).filter_map(move |x| buffer.cell((x, region.y)))
+        })
+        .cloned()
+        .collect()
+}
+
+// Re-prints the cells under each region wrapped in OSC 8 so the terminal makes them clickable.
+// Ratatui has no notion of hyperlinks, so this runs after it has flushed the frame.
+pub fn emit_hyperlinks(
+    backend: &mut CrosstermBackend<io::Stdout>,
+    buffer: &Buffer,
+    regions: &[LinkRegion],
+) -> io::Result<()> {
+    queue!(backend, SavePosition)?;
+    for region in regions {
+        queue!(
+            backend,
+            MoveTo(region.x, region.y),
+            Print(format!("\x1b]8;;{}\x1b\\", normalize(&region.url)))
+        )?;
+        let mut x = region.x;
+        while x < region.x + region.width {
+            let Some(cell) = buffer.cell((x, region.y)) else {
+                break;
+            };
+            // Wide characters cover the cells after them, just like ratatui's own diff
+            x += cell.symbol().width().max(1) as u16;
+            queue!(backend, SetAttribute(Attribute::Reset))?;
+            for (modifier, attribute) in [
+                (Modifier::BOLD, Attribute::Bold),
+                (Modifier::DIM, Attribute::Dim),
+                (Modifier::ITALIC, Attribute::Italic),
+                (Modifier::UNDERLINED, Attribute::Underlined),
+                (Modifier::REVERSED, Attribute::Reverse),
+                (Modifier::CROSSED_OUT, Attribute::CrossedOut),
+            ] {
+                if cell.modifier.contains(modifier) {
+                    queue!(backend, SetAttribute(attribute))?;
+                }
+            }
+            queue!(
+                backend,
+                SetForegroundColor(cell.fg.into()),
+                SetBackgroundColor(cell.bg.into()),
+                Print(cell.symbol())
+            )?;
+        }
+        queue!(
+            backend,
+            Print("\x1b]8;;\x1b\\"),
+            SetAttribute(Attribute::Reset)
+        )?;
+    }
+    queue!(backend, RestorePosition)?;
+    backend.flush()
+}
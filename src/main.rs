@@ -1,36 +1,161 @@
-use color_eyre::Result;
+mod actions;
+mod clipboard;
+mod commands;
+mod complete;
+mod config;
+mod editor;
+mod emoji;
+mod history;
+mod keymap;
+mod links;
+mod markdown;
+mod presence;
+mod term;
+mod theme;
+mod time;
+mod transcript;
+mod vi;
+
+use actions::{MenuItems, MessageAction, MessageMenu};
+use color_eyre::{Result, eyre::eyre};
+use commands::{Command, CopyTarget};
+use config::Config;
+use editor::{LineEditor, Outcome};
 use futures_util::{SinkExt, StreamExt};
+use history::History;
+use keymap::Action;
+use links::LinkRegion;
+use presence::PresenceUser;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use ratatui::prelude::Margin;
 use ratatui::{
     DefaultTerminal, Frame,
-    crossterm::event::{self, Event, KeyCode, KeyEventKind},
-    layout::{Constraint, Layout, Position},
-    style::{Color, Modifier, Style, Stylize},
+    crossterm::event::{
+        self, Event, KeyCode, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
+    layout::{Constraint, Flex, Layout, Position, Rect, Size},
+    style::{Modifier, Style, Stylize},
     text::{Line, Span, Text},
     widgets::{
-        Block, BorderType, Borders, List, ListItem, Padding, Paragraph, Scrollbar,
-        ScrollbarOrientation, ScrollbarState, Wrap,
+        Block, BorderType, Borders, Clear, List, ListItem, ListState, Padding, Paragraph,
+        Scrollbar, ScrollbarOrientation,
     },
 };
 use serde::{Deserialize, Serialize};
-use std::io::{Read, Write};
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::ControlFlow;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use theme::{ColorDepth, Theme};
 use tokio::sync::mpsc;
 use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use transcript::{LayoutStyle, Transcript};
+use unicode_width::UnicodeWidthStr;
 
 // Message format for WebSocket communication
 #[derive(Serialize, Deserialize, Clone, Debug)]
 struct ChatMessage {
+    // Given by the server once it has stored the message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
     content: String,
     #[serde(rename = "authorId")]
     author_id: String,
     timestamp: u64,
 }
 
+// Typing notification relayed by the server for another client
+#[derive(Deserialize, Clone, Debug)]
+struct TypingEvent {
+    #[serde(rename = "authorId")]
+    author_id: String,
+    typing: bool,
+}
+
+// Structured notices from the server, tagged by their "type" field
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerNotice {
+    Typing(TypingEvent),
+    // Everyone online, sent once right after we connect
+    Presence {
+        users: Vec<PresenceUser>,
+    },
+    Join {
+        user: PresenceUser,
+    },
+    Leave {
+        #[serde(rename = "userId")]
+        user_id: String,
+    },
+    // Someone changed or took back a message they sent
+    Edit {
+        id: u64,
+        content: String,
+    },
+    Delete {
+        id: u64,
+    },
+}
+
+// Anything the server can push to us over the WebSocket
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum ServerEvent {
+    Notice(ServerNotice),
+    Chat(ChatMessage),
+    // The server turned down something we sent
+    Error { error: String },
+}
+
+// Anything the UI can ask the WebSocket task to send
+#[derive(Debug)]
+enum ClientEvent {
+    Chat(String),
+    Typing(bool),
+    Edit { id: u64, content: String },
+    Delete(u64),
+}
+
 const WEBSERVER_URL: &str = "https://protective-giacinta-arnavk-09-6c1478d5.koyeb.app";
 
+// Minimum gap between two typing-start notifications we send
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+// How long the input can sit untouched before we send typing-stop
+const TYPING_IDLE: Duration = Duration::from_secs(5);
+// How long someone else's typing notice stays visible without a refresh
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+// How long a notice replaces the help line
+const NOTICE_DURATION: Duration = Duration::from_secs(3);
+// Pastes longer than this many lines, or bytes, ask what to do with them first
+const PASTE_CONFIRM_LINES: usize = 5;
+const PASTE_CONFIRM_BYTES: usize = 2000;
+// Lines one notch of the mouse wheel scrolls
+const WHEEL_LINES: usize = 3;
+// Terminals shorter than this get a one-line header
+const SHORT_HEIGHT: u16 = 24;
+// ...and shorter than this lose the help line too, with notices moving to the header
+const TINY_HEIGHT: u16 = 16;
+// Terminals at least this wide show who's online without pressing `u`
+const WIDE_WIDTH: u16 = 120;
+const SIDEBAR_WIDTH: u16 = 32;
+
+// Choices offered for a long paste
+#[derive(Clone, Copy)]
+enum PasteAction {
+    Send,
+    SendAsCode,
+    Insert,
+}
+
+const PASTE_ACTIONS: [(PasteAction, &str); 3] = [
+    (PasteAction::Send, "Send as one message"),
+    (PasteAction::SendAsCode, "Send as a code block"),
+    (PasteAction::Insert, "Put it in the input box"),
+];
+
 // Add this function to generate fun usernames
 fn generate_fun_username() -> String {
     let adjectives = [
@@ -73,6 +198,8 @@ fn generate_fun_username() -> String {
 // In main(), replace the UUID generation with:
 fn main() -> Result<()> {
     color_eyre::install()?;
+    let config = config::load()?;
+    let theme = Theme::load(&config.theme).map_err(|e| eyre!(e))?;
 
     // Generate a fun user ID for this client
     let user_id = generate_fun_username();
@@ -83,8 +210,8 @@ fn main() -> Result<()> {
     let rt = tokio::runtime::Runtime::new()?;
 
     // Create channels for communication between UI and WebSocket
-    let (ws_tx, mut ws_rx) = mpsc::channel::<String>(100);
-    let (msg_tx, msg_rx) = mpsc::channel::<ChatMessage>(100);
+    let (ws_tx, mut ws_rx) = mpsc::channel::<ClientEvent>(100);
+    let (msg_tx, msg_rx) = mpsc::channel::<ServerEvent>(100);
 
     // Shared state for connection status
     let connection_status = Arc::new(Mutex::new(String::from("Connecting...")));
@@ -115,12 +242,11 @@ fn main() -> Result<()> {
                 tokio::spawn(async move {
                     while let Some(message) = read.next().await {
                         if let Ok(msg) = message {
-                            if let Message::Text(text) = msg {
-                                if let Ok(chat_msg) = serde_json::from_str::<ChatMessage>(&text) {
-                                    if let Err(_) = msg_tx_clone.send(chat_msg).await {
-                                        break;
-                                    }
-                                }
+                            if let Message::Text(text) = msg
+                                && let Ok(event) = serde_json::from_str::<ServerEvent>(&text)
+                                && msg_tx_clone.send(event).await.is_err()
+                            {
+                                break;
                             }
                         } else {
                             break;
@@ -129,20 +255,32 @@ fn main() -> Result<()> {
                 });
 
                 // Handle outgoing messages to the WebSocket
-                while let Some(message) = ws_rx.recv().await {
-                    let chat_msg = ChatMessage {
-                        content: message.clone(),
-                        author_id: user_id_for_ws.clone(),
-                        timestamp: std::time::SystemTime::now()
-                            .duration_since(std::time::UNIX_EPOCH)
-                            .unwrap()
-                            .as_millis() as u64,
+                while let Some(event) = ws_rx.recv().await {
+                    let json = match event {
+                        ClientEvent::Chat(message) => {
+                            let chat_msg = ChatMessage {
+                                id: None,
+                                content: message,
+                                author_id: user_id_for_ws.clone(),
+                                timestamp: time::now_millis(),
+                            };
+                            serde_json::to_string(&chat_msg)
+                        }
+                        ClientEvent::Typing(typing) => serde_json::to_string(
+                            &serde_json::json!({ "type": "typing", "typing": typing }),
+                        ),
+                        ClientEvent::Edit { id, content } => serde_json::to_string(
+                            &serde_json::json!({ "type": "edit", "id": id, "content": content }),
+                        ),
+                        ClientEvent::Delete(id) => serde_json::to_string(
+                            &serde_json::json!({ "type": "delete", "id": id }),
+                        ),
                     };
 
-                    if let Ok(json) = serde_json::to_string(&chat_msg) {
-                        if let Err(_) = write.send(Message::Text(json)).await {
-                            break;
-                        }
+                    if let Ok(json) = json
+                        && write.send(Message::Text(json)).await.is_err()
+                    {
+                        break;
                     }
                 }
             }
@@ -155,499 +293,2369 @@ fn main() -> Result<()> {
 
     // Initialize the terminal UI
     let terminal = ratatui::init();
+    let modes = term::Modes::detect(config.mouse);
+    modes.enable()?;
 
     // Create and run the app
-    let app = App::new(ws_tx, msg_rx, connection_status, user_id);
-    let connected_users = app.connected_users.clone();
-
-    // Spawn a task to periodically update the user count
-    rt.spawn(async move {
-        loop {
-            let host = WEBSERVER_URL.split("/").last().unwrap_or("localhost:3000");
-            if let Ok(mut stream) = std::net::TcpStream::connect(host) {
-                let request = format!(
-                    "GET /users HTTP/1.1\r\n\
-                    Host: {}\r\n\
-                    Connection: close\r\n\
-                    \r\n",
-                    host
-                );
-
-                if stream.write_all(request.as_bytes()).is_ok() {
-                    let mut response = String::new();
-                    if let Ok(_) = std::io::BufReader::new(&stream).read_to_string(&mut response) {
-                        if let Some(body_start) = response.find("\r\n\r\n") {
-                            if let Ok(json) = serde_json::from_str::<serde_json::Value>(
-                                &response[body_start + 4..],
-                            ) {
-                                if let Some(count) = json["count"].as_u64() {
-                                    let mut users = connected_users.lock().unwrap();
-                                    *users = count as usize;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            std::thread::sleep(std::time::Duration::from_secs(5));
-        }
-    });
-
+    let mut app = App::new(
+        config,
+        modes,
+        theme,
+        ws_tx,
+        msg_rx,
+        connection_status,
+        user_id,
+    );
     let app_result = app.run(terminal, rt);
 
+    app.modes.disable()?;
     ratatui::restore();
     app_result
 }
 
 struct App {
-    input: String,
-    character_index: usize,
+    config: Config,
+    modes: term::Modes,
+    // Colors in use, already fitted to what the terminal can show
+    theme: Theme,
+    color_depth: ColorDepth,
+    input: LineEditor,
+    // Vi normal-mode state, used when the config turns vi mode on
+    vi: vi::Vi,
+    // Messages we've sent, for Up/Down recall and Ctrl-R search
+    history: History,
+    history_search: Option<history::Search>,
+    // First line and display column the input box shows, once the text outgrows it
+    input_scroll: (usize, usize),
     input_mode: InputMode,
-    messages: Vec<(String, bool, String)>,
-    messages_scroll_state: ScrollbarState,
-    messages_scroll: usize,
-    ws_tx: mpsc::Sender<String>,
-    msg_rx: mpsc::Receiver<ChatMessage>,
+    transcript: Transcript,
+    ws_tx: mpsc::Sender<ClientEvent>,
+    msg_rx: mpsc::Receiver<ServerEvent>,
     connection_status: Arc<Mutex<String>>,
     user_id: String,
-    connected_users: Arc<Mutex<usize>>,
+    // Everyone online, kept live from presence/join/leave notices
+    presence: BTreeMap<String, PresenceUser>,
+    // Set once `u` is pressed; until then the sidebar comes and goes with the width
+    show_sidebar: Option<bool>,
+    // How the frame is divided up at the current terminal size
+    screen: Screen,
+    sidebar_focused: bool,
+    sidebar_state: ListState,
+    // User whose whois card is open
+    whois: Option<String>,
+    // Short-lived feedback shown in place of the help line
+    notice: Option<(String, Instant)>,
+    // Links of the message picked with `o`, waiting for one to be opened
+    url_picker: Option<(Vec<String>, ListState)>,
+    // Actions for the message picked in selection mode
+    message_menu: Option<MessageMenu>,
+    // Message whose details card is open
+    details: Option<usize>,
+    // The key overlay from `?` is open
+    show_keys: bool,
+    // Server id of the message being edited, and the draft it pushed aside
+    editing: Option<(u64, String)>,
+    // Hyperlinks last written to the terminal, the screen size they were written for and
+    // the cells they were written over
+    link_regions: Vec<LinkRegion>,
+    link_area: Rect,
+    link_cells: Vec<ratatui::buffer::Cell>,
+    // Where the last frame put things, for working out what a click landed on
+    areas: Cell<Areas>,
+    // The scrollbar thumb is being dragged
+    dragging_scrollbar: bool,
+    // Shortcode autocomplete popup, and the `:` position the user last dismissed it at
+    emoji_picker: Option<emoji::Picker>,
+    emoji_dismissed_at: Option<usize>,
+    // A long paste waiting for the user to pick a PasteAction
+    paste_prompt: Option<(String, ListState)>,
+    // Ctrl-X was pressed and we're waiting for the rest of the chord
+    ctrl_x_pending: bool,
+    // Tab-completion popup, while Tab keeps cycling through candidates
+    completion: Option<complete::Completion>,
+    recent_emoji: emoji::Recent,
+    // When we last told the server we're typing, None while we're idle
+    typing_sent_at: Option<Instant>,
+    last_edit_at: Option<Instant>,
+    // Other users currently typing, with when we last heard from them
+    typing_users: HashMap<String, Instant>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Screen {
+    // One line instead of the bordered five
+    short_header: bool,
+    help_line: bool,
+    sidebar: bool,
+    // Most rows the input box may take, so it can't crowd out the messages
+    input_rows: usize,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Areas {
+    // Inside the border and padding
+    messages: Rect,
+    scrollbar: Rect,
+    // Inside the border
+    input: Rect,
+    // The "↓ 12 new" badge on the bottom border of the messages, when there is one
+    unread_badge: Rect,
 }
 
 enum InputMode {
     Normal,
     Editing,
+    // Moving a cursor over messages to act on one
+    Selecting,
 }
 
 impl App {
     fn new(
-        ws_tx: mpsc::Sender<String>,
-        msg_rx: mpsc::Receiver<ChatMessage>,
+        config: Config,
+        modes: term::Modes,
+        theme: Theme,
+        ws_tx: mpsc::Sender<ClientEvent>,
+        msg_rx: mpsc::Receiver<ServerEvent>,
         connection_status: Arc<Mutex<String>>,
         user_id: String,
     ) -> Self {
+        let color_depth = ColorDepth::detect();
+        let theme = theme.adapted(color_depth);
+        let transcript = Transcript::new(
+            theme.clone(),
+            markdown::Styles::new(&theme, color_depth),
+            config.layout,
+            config.max_bubble_width,
+        );
         Self {
-            input: String::new(),
+            config,
+            modes,
+            transcript,
+            theme,
+            color_depth,
+            input: LineEditor::new(),
+            vi: vi::Vi::default(),
+            history: History::load(config::state_dir().map(|dir| dir.join("history"))),
+            history_search: None,
+            input_scroll: (0, 0),
             input_mode: InputMode::Editing,
-            messages: Vec::new(),
-            character_index: 0,
-            messages_scroll_state: ScrollbarState::default(),
-            messages_scroll: 0,
             ws_tx,
             msg_rx,
             connection_status,
             user_id,
-            connected_users: Arc::new(Mutex::new(1)),
+            presence: BTreeMap::new(),
+            show_sidebar: None,
+            screen: Screen::default(),
+            sidebar_focused: false,
+            sidebar_state: ListState::default(),
+            whois: None,
+            notice: None,
+            url_picker: None,
+            message_menu: None,
+            details: None,
+            show_keys: false,
+            editing: None,
+            link_regions: Vec::new(),
+            link_area: Rect::default(),
+            link_cells: Vec::new(),
+            areas: Cell::default(),
+            dragging_scrollbar: false,
+            emoji_picker: None,
+            emoji_dismissed_at: None,
+            paste_prompt: None,
+            ctrl_x_pending: false,
+            completion: None,
+            recent_emoji: emoji::Recent::default(),
+            typing_sent_at: None,
+            last_edit_at: None,
+            typing_users: HashMap::new(),
         }
     }
 
-    fn move_cursor_left(&mut self) {
-        let cursor_moved_left = self.character_index.saturating_sub(1);
-        self.character_index = self.clamp_cursor(cursor_moved_left);
+    // Sends the input as a message, or runs it when it's a slash command
+    fn submit_message(&mut self) -> ControlFlow<()> {
+        let text = self.input.text().to_string();
+        if text.trim().is_empty() {
+            return ControlFlow::Continue(());
+        }
+        if let Some((id, _)) = self.editing {
+            let content = emoji::expand_shortcodes(&text, &mut self.recent_emoji);
+            if self.send(ClientEvent::Edit { id, content }) {
+                self.stop_typing();
+                self.finish_editing();
+            }
+            return ControlFlow::Continue(());
+        }
+        match commands::parse(&text) {
+            Some(Ok(command)) => {
+                self.clear_sent_input();
+                return self.run_command(command);
+            }
+            // Leave the input alone so the typo can be fixed
+            Some(Err(e)) => self.notify(e),
+            None => {
+                let message =
+                    emoji::expand_shortcodes(commands::unescape(&text), &mut self.recent_emoji);
+                if self.send_chat(message) {
+                    self.clear_sent_input();
+                }
+            }
+        }
+        ControlFlow::Continue(())
     }
 
-    fn move_cursor_right(&mut self) {
-        let cursor_moved_right = self.character_index.saturating_add(1);
-        self.character_index = self.clamp_cursor(cursor_moved_right);
+    fn send_chat(&mut self, message: String) -> bool {
+        self.send(ClientEvent::Chat(message))
     }
 
-    fn enter_char(&mut self, new_char: char) {
-        let index = self.byte_index();
-        self.input.insert(index, new_char);
-        self.move_cursor_right();
+    // Hands an event to the WebSocket task, telling the user when that fails
+    fn send(&mut self, event: ClientEvent) -> bool {
+        match self.ws_tx.try_send(event) {
+            Ok(()) => true,
+            Err(_) => {
+                self.notify("Couldn't send that, the connection is backed up");
+                false
+            }
+        }
     }
 
-    fn byte_index(&self) -> usize {
-        self.input
-            .char_indices()
-            .map(|(i, _)| i)
-            .nth(self.character_index)
-            .unwrap_or(self.input.len())
+    // A card, menu or prompt is up and has the keyboard to itself
+    fn popup_open(&self) -> bool {
+        self.whois.is_some()
+            || self.details.is_some()
+            || self.show_keys
+            || self.message_menu.is_some()
+            || self.url_picker.is_some()
+            || self.paste_prompt.is_some()
     }
 
-    fn delete_char(&mut self) {
-        let is_not_cursor_leftmost = self.character_index != 0;
-        if is_not_cursor_leftmost {
-            let current_index = self.character_index;
-            let from_left_to_current_index = current_index - 1;
-            let before_char_to_delete = self.input.chars().take(from_left_to_current_index);
-            let after_char_to_delete = self.input.chars().skip(current_index);
-            self.input = before_char_to_delete.chain(after_char_to_delete).collect();
-            self.move_cursor_left();
+    // A bracketed paste: short ones go straight into the input, long ones ask first
+    fn handle_paste(&mut self, text: String) {
+        if self.popup_open() {
+            return;
+        }
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.input_mode = InputMode::Editing;
+        self.history_search = None;
+        self.completion = None;
+        // Its start offset would be stale once the paste is in
+        self.emoji_picker = None;
+        if text.lines().count() > PASTE_CONFIRM_LINES || text.len() > PASTE_CONFIRM_BYTES {
+            self.paste_prompt = Some((text, ListState::default().with_selected(Some(0))));
+        } else {
+            self.input.insert_str(&text);
+            self.notify_typing();
         }
     }
 
-    fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
-        new_cursor_pos.clamp(0, self.input.chars().count())
+    // Sending goes through the input like anything typed, so an edit in progress, shortcodes
+    // and history all apply
+    fn finish_paste(&mut self, index: Option<usize>) -> ControlFlow<()> {
+        let Some((text, state)) = self.paste_prompt.take() else {
+            return ControlFlow::Continue(());
+        };
+        let Some((action, _)) = index
+            .or(state.selected())
+            .and_then(|index| PASTE_ACTIONS.get(index))
+        else {
+            self.paste_prompt = Some((text, state));
+            return ControlFlow::Continue(());
+        };
+        match action {
+            PasteAction::Send => {
+                self.input.insert_str(&text);
+                self.submit_message()
+            }
+            PasteAction::SendAsCode => {
+                // The fence has to be longer than any backtick run inside the paste
+                let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+                let fence = "`".repeat(longest.max(2) + 1);
+                // and start a line of its own
+                let cursor = self.input.cursor();
+                let newline = if cursor > self.input.line_start(cursor) {
+                    "\n"
+                } else {
+                    ""
+                };
+                self.input.insert_str(&format!(
+                    "{}{}\n{}\n{}",
+                    newline,
+                    fence,
+                    text.trim_end_matches('\n'),
+                    fence
+                ));
+                self.submit_message()
+            }
+            PasteAction::Insert => {
+                self.input.insert_str(&text);
+                self.notify_typing();
+                ControlFlow::Continue(())
+            }
+        }
     }
 
-    fn reset_cursor(&mut self) {
-        self.character_index = 0;
+    fn clear_sent_input(&mut self) {
+        let sent = self.input.take();
+        self.stop_typing();
+        if let Err(e) = self.history.push(&sent) {
+            self.notify(format!("Couldn't save history: {}", e));
+        }
     }
 
-    fn submit_message(&mut self) {
-        if !self.input.trim().is_empty() {
-            // Send message to WebSocket
-            let message = self.input.clone();
-            if let Ok(_) = self.ws_tx.try_send(message) {
-                // Clear input after sending
-                self.input.clear();
-                self.reset_cursor();
+    fn run_command(&mut self, command: Command) -> ControlFlow<()> {
+        match command {
+            Command::Help => self.notify(commands::help()),
+            Command::Users => self.toggle_sidebar(),
+            Command::Whois(user) => {
+                if self.known_users().contains(&user) || user == self.user_id {
+                    self.whois = Some(user);
+                } else {
+                    self.notify(format!("No one called {} around here", user));
+                }
             }
+            Command::Links => self.open_url_picker(),
+            Command::Copy(CopyTarget::Code) => self.copy_code_block(),
+            Command::Copy(CopyTarget::Message) => self.copy_message(),
+            Command::Copy(CopyTarget::Screen) => self.copy_screen(),
+            Command::Theme(None) => self.notify(format!(
+                "Themes: {} – or put your own in {}",
+                theme::BUILT_IN.join(", "),
+                config::config_dir().map_or("themes/".into(), |dir| dir
+                    .join("themes")
+                    .display()
+                    .to_string())
+            )),
+            Command::Theme(Some(name)) => match Theme::load(&name) {
+                Ok(theme) => {
+                    self.theme = theme.adapted(self.color_depth);
+                    self.transcript.restyle(
+                        self.theme.clone(),
+                        markdown::Styles::new(&self.theme, self.color_depth),
+                    );
+                    self.notify(format!("Switched to the {} theme", name));
+                }
+                Err(e) => self.notify(e),
+            },
+            Command::Compact => self.toggle_layout(),
+            Command::Mouse => self.toggle_mouse(),
+            Command::Quit => return ControlFlow::Break(()),
         }
+        ControlFlow::Continue(())
     }
 
-    fn append_message(&mut self, message: String, from_user: bool, author_id: String) {
-        self.messages.push((message, from_user, author_id));
-        self.messages_scroll = self.messages.len().saturating_sub(1);
-        self.messages_scroll_state = self.messages_scroll_state.position(self.messages_scroll);
+    // Everyone else we've seen, online now or in the scrollback, for @mentions
+    fn known_users(&self) -> Vec<String> {
+        let authors = self
+            .transcript
+            .messages()
+            .iter()
+            .filter(|message| message.is_chat())
+            .map(|message| &message.author);
+        self.presence
+            .keys()
+            .chain(authors)
+            .filter(|user| **user != self.user_id)
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
     }
 
-    fn run(mut self, mut terminal: DefaultTerminal, _rt: tokio::runtime::Runtime) -> Result<()> {
-        // Remove the EventStream line that's causing the error
-        // let mut event_reader = event::EventStream::new();
+    // Ctrl-X Ctrl-E: suspends the UI and edits the input in $VISUAL/$EDITOR, loading
+    // whatever was saved back into the input box
+    fn compose_in_editor(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        let path = std::env::temp_dir().join(format!(
+            "global-chat-{}-{}.md",
+            std::process::id(),
+            time::now_millis()
+        ));
+        if let Err(e) = write_draft(&path, self.input.text()) {
+            self.notify(format!("Couldn't create a draft file: {}", e));
+            return Ok(());
+        }
 
-        loop {
-            // Check for new messages from WebSocket
-            if let Ok(msg) = self.msg_rx.try_recv() {
-                let is_from_user = msg.author_id == self.user_id;
-                self.append_message(msg.content, is_from_user, msg.author_id);
+        self.stop_typing();
+        self.modes.suspend()?;
+        // $EDITOR may carry flags, like "code --wait"
+        let mut words = editor.split_whitespace();
+        let status = std::process::Command::new(words.next().unwrap_or("vi"))
+            .args(words)
+            .arg(&path)
+            .status();
+        self.modes.resume(terminal)?;
+        // The screen was wiped, so the hyperlinks need writing again
+        self.link_regions.clear();
+
+        match status {
+            Ok(status) if status.success() => match std::fs::read_to_string(&path) {
+                Ok(text) => {
+                    // Editors like to end files with a newline nobody meant to send
+                    let text = text.trim_end_matches(['\n', '\r']);
+                    self.input.set_text(text, text.len());
+                }
+                Err(e) => self.notify(format!("Couldn't read the draft back: {}", e)),
+            },
+            Ok(status) => self.notify(format!("{} exited with {}, draft dropped", editor, status)),
+            Err(e) => self.notify(format!("Couldn't start {}: {}", editor, e)),
+        }
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    // Tab/Shift-Tab: completes the word before the cursor, or cycles the open completion
+    fn complete(&mut self, backwards: bool) {
+        if let Some(completion) = &mut self.completion {
+            if backwards {
+                completion.select_previous();
+            } else {
+                completion.select_next();
             }
+            let (start, end) = (completion.start, completion.end);
+            let replacement = completion.current().replacement.clone();
+            completion.end = start + replacement.len();
+            self.input.replace_range(start, end, &replacement);
+            return;
+        }
 
-            terminal.draw(|frame| self.draw(frame))?;
+        let cursor = self.input.cursor();
+        let users = self.known_users();
+        let Some((start, candidates)) =
+            complete::candidates(self.input.text(), cursor, &users, &self.recent_emoji)
+        else {
+            return;
+        };
+        let mut completion = complete::Completion {
+            start,
+            end: cursor,
+            original: self.input.text()[start..cursor].to_string(),
+            candidates,
+            selected: 0,
+        };
+        // Carry over the emoji the picker had highlighted
+        if let Some(picker) = &self.emoji_picker {
+            completion.selected = picker.selected.min(completion.candidates.len() - 1);
+        }
+        let replacement = completion.current().replacement.clone();
+        completion.end = start + replacement.len();
+        self.input.replace_range(start, cursor, &replacement);
+        // A single candidate is simply filled in
+        if completion.candidates.len() > 1 {
+            self.completion = Some(completion);
+        }
+    }
 
-            // Use poll with a timeout to make the UI responsive without blocking
-            if event::poll(std::time::Duration::from_millis(100))? {
-                if let Event::Key(key) = event::read()? {
-                    match self.input_mode {
-                        InputMode::Normal => match key.code {
-                            KeyCode::Enter => {
-                                self.input_mode = InputMode::Editing;
-                            }
-                            KeyCode::Char('q') => {
-                                return Ok(());
-                            }
-                            KeyCode::Up | KeyCode::Char('k') => {
-                                self.scroll_messages_up();
-                            }
-                            KeyCode::Down | KeyCode::Char('j') => {
-                                self.scroll_messages_down();
-                            }
-                            _ => {}
-                        },
-                        InputMode::Editing if key.kind == KeyEventKind::Press => match key.code {
-                            KeyCode::Enter => self.submit_message(),
-                            KeyCode::Backspace => self.delete_char(),
-                            KeyCode::Left => self.move_cursor_left(),
-                            KeyCode::Right => self.move_cursor_right(),
-                            KeyCode::Up => {
-                                self.scroll_messages_up();
-                            }
-                            KeyCode::Down => {
-                                self.scroll_messages_down();
-                            }
-                            KeyCode::Esc => self.input_mode = InputMode::Normal,
-                            KeyCode::Char('q')
-                                if key.modifiers.contains(event::KeyModifiers::CONTROL) =>
-                            {
-                                return Ok(());
-                            }
-                            KeyCode::Char(to_insert) => self.enter_char(to_insert),
-                            _ => {}
-                        },
-                        InputMode::Editing => {}
-                    }
-                }
+    // Esc while completing: put back what was typed
+    fn cancel_completion(&mut self) {
+        if let Some(completion) = self.completion.take() {
+            self.input
+                .replace_range(completion.start, completion.end, &completion.original);
+        }
+    }
+
+    // Up/Ctrl-P on an empty or just-recalled input steps back through sent messages
+    fn recall_previous(&mut self) -> bool {
+        match self.history.previous(self.input.text()) {
+            Some(entry) => {
+                let entry = entry.to_string();
+                self.input.set_text(&entry, entry.len());
+                true
             }
+            None => false,
         }
     }
 
-    fn scroll_messages_up(&mut self) {
-        if self.messages_scroll > 0 {
-            self.messages_scroll -= 1;
-            self.messages_scroll_state = self.messages_scroll_state.position(self.messages_scroll);
+    fn recall_next(&mut self) -> bool {
+        match self.history.next(self.input.text()) {
+            Some(entry) => {
+                let entry = entry.to_string();
+                self.input.set_text(&entry, entry.len());
+                true
+            }
+            None => false,
         }
     }
 
-    fn scroll_messages_down(&mut self) {
-        if self.messages_scroll < self.messages.len().saturating_sub(1) {
-            self.messages_scroll += 1;
-            self.messages_scroll_state = self.messages_scroll_state.position(self.messages_scroll);
+    fn start_history_search(&mut self) {
+        self.history_search = Some(history::Search {
+            query: String::new(),
+            original: self.input.text().to_string(),
+            found: None,
+        });
+    }
+
+    // Looks for the search query in entries older than `before`, showing the match in the input
+    fn search_history(&mut self, before: usize) {
+        let Some(search) = &mut self.history_search else {
+            return;
+        };
+        if search.query.is_empty() {
+            search.found = None;
+            return;
+        }
+        // A failed search leaves the last match in the input, like readline
+        search.found = self.history.search(&search.query, before);
+        if let Some((index, offset)) = search.found {
+            let entry = self.history.entry(index).to_string();
+            self.input.set_text(&entry, offset);
         }
     }
 
-    fn draw(&self, frame: &mut Frame) {
-        let vertical = Layout::vertical([
-            Constraint::Length(5),
-            Constraint::Min(1),
-            Constraint::Length(3),
-            Constraint::Length(1),
-        ]);
-        let [title_area, messages_area, input_area, help_area] = vertical.areas(frame.area());
+    // Esc/Ctrl-G: puts back whatever was typed before the search
+    fn cancel_history_search(&mut self) {
+        if let Some(search) = self.history_search.take() {
+            self.input.set_text(&search.original, search.original.len());
+        }
+    }
 
-        let bg_block = Block::default().style(Style::default().bg(Color::Rgb(13, 20, 24)));
-        frame.render_widget(bg_block, frame.area());
+    fn handle_history_search_key(&mut self, key: event::KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let Some(search) = &mut self.history_search else {
+            return;
+        };
+        match key.code {
+            // Again: step to an older match
+            KeyCode::Char('r') if ctrl => {
+                let before = search.found.map_or(self.history.len(), |(index, _)| index);
+                self.search_history(before);
+            }
+            KeyCode::Esc => self.cancel_history_search(),
+            KeyCode::Char('g') if ctrl => self.cancel_history_search(),
+            KeyCode::Char(c) if !ctrl => {
+                search.query.push(c);
+                // A longer query can still match the entry we're on
+                let before = search
+                    .found
+                    .map_or(self.history.len(), |(index, _)| index + 1);
+                self.search_history(before);
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                self.search_history(self.history.len());
+            }
+            // Anything else keeps the match and goes back to editing
+            _ => self.history_search = None,
+        }
+    }
 
-        // Get connection status
-        let status = self.connection_status.lock().unwrap().clone();
+    // Opens, refreshes or closes the emoji picker to match the `:shortcode` before the cursor
+    fn update_emoji_picker(&mut self) {
+        let Some((start, query)) = emoji::shortcode_at(self.input.text(), self.input.cursor())
+        else {
+            self.emoji_picker = None;
+            self.emoji_dismissed_at = None;
+            return;
+        };
+        if self.emoji_dismissed_at == Some(start) {
+            return;
+        }
 
-        // Create a two-line title with status and user ID on separate lines
-        let title_text = vec![
-            Line::from(vec![Span::styled(
-                "💬 Global Chat 💬",
-                Style::default()
-                    .fg(Color::Rgb(0, 230, 118))
-                    .add_modifier(Modifier::BOLD),
-            )]),
-            Line::from(vec![
-                Span::styled("Status: ", Style::default().fg(Color::White)),
-                Span::styled(&status, Style::default().fg(Color::Rgb(0, 230, 118))),
-            ]),
-            Line::from(vec![
-                Span::styled("Your ID: ", Style::default().fg(Color::White)),
-                Span::styled(&self.user_id, Style::default().fg(Color::Rgb(0, 230, 118))),
-            ]),
-        ];
+        let matches = emoji::search(&query, &self.recent_emoji);
+        self.emoji_picker = (!matches.is_empty()).then_some(emoji::Picker {
+            start,
+            matches,
+            selected: 0,
+        });
+    }
 
-        let title = Paragraph::new(title_text)
-            .style(Style::default().bg(Color::Rgb(17, 27, 33)))
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(Color::Rgb(69, 90, 100))),
-            )
-            .alignment(ratatui::layout::Alignment::Center);
-        frame.render_widget(title, title_area);
+    fn accept_emoji(&mut self) {
+        let Some(picker) = self.emoji_picker.take() else {
+            return;
+        };
+        let Some(emoji) = picker.selected() else {
+            return;
+        };
+        self.recent_emoji.record(emoji);
 
-        let messages_len = self.messages.len();
-        let mut messages_scroll_state = self.messages_scroll_state.content_length(messages_len);
+        let cursor = self.input.cursor();
+        self.input
+            .replace_range(picker.start, cursor, emoji.as_str());
+        self.notify_typing();
+    }
 
-        let visible_height = messages_area.height.saturating_sub(2) as usize;
+    // Called after every edit: sends a throttled typing-start, or a stop once the input is empty
+    fn notify_typing(&mut self) {
+        if self.input.is_empty() {
+            self.stop_typing();
+            return;
+        }
 
-        // Calculate total lines needed for all messages to determine proper scrolling
-        let mut total_lines = 0;
-        let messages_with_line_counts: Vec<(usize, &(String, bool, String))> = self
-            .messages
-            .iter()
-            .map(|msg| {
-                let available_width = messages_area.width.saturating_sub(10) as usize;
-                let wrapped_lines = textwrap::wrap(&msg.0, available_width).len();
-                // Count the message lines plus spacing
-                let line_count = wrapped_lines + 1; // +1 for spacing
-                total_lines += line_count;
-                (line_count, msg)
-            })
-            .collect();
+        let now = Instant::now();
+        self.last_edit_at = Some(now);
+        let should_send = self
+            .typing_sent_at
+            .is_none_or(|sent| now.duration_since(sent) >= TYPING_THROTTLE);
+        if should_send && self.ws_tx.try_send(ClientEvent::Typing(true)).is_ok() {
+            self.typing_sent_at = Some(now);
+        }
+    }
+
+    fn stop_typing(&mut self) {
+        if self.typing_sent_at.take().is_some() {
+            let _ = self.ws_tx.try_send(ClientEvent::Typing(false));
+        }
+        self.last_edit_at = None;
+    }
+
+    // Sends typing-stop after we go idle and forgets typists we haven't heard from in a while
+    fn expire_typing(&mut self) {
+        if self
+            .last_edit_at
+            .is_some_and(|edited| edited.elapsed() >= TYPING_IDLE)
+        {
+            self.stop_typing();
+        }
+        self.typing_users
+            .retain(|_, seen| seen.elapsed() < TYPING_TIMEOUT);
+    }
+
+    fn handle_typing_event(&mut self, event: TypingEvent) {
+        if event.author_id == self.user_id {
+            return;
+        }
+        if event.typing {
+            self.typing_users.insert(event.author_id, Instant::now());
+        } else {
+            self.typing_users.remove(&event.author_id);
+        }
+    }
+
+    fn typing_indicator(&self) -> Option<String> {
+        let mut names: Vec<&str> = self.typing_users.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        match names.as_slice() {
+            [] => None,
+            [one] => Some(format!("{} is typing…", one)),
+            [first, second] => Some(format!("{} and {} are typing…", first, second)),
+            _ => Some(String::from("Several people are typing…")),
+        }
+    }
 
-        // Determine which messages to show based on scroll position
-        let mut lines_from_top = 0;
-        let mut start_idx = 0;
+    fn handle_notice(&mut self, notice: ServerNotice) {
+        match notice {
+            ServerNotice::Typing(typing) => {
+                if typing.typing {
+                    self.mark_active(&typing.author_id);
+                }
+                self.handle_typing_event(typing);
+            }
+            ServerNotice::Presence { users } => {
+                self.presence = users
+                    .into_iter()
+                    .map(|user| (user.id.clone(), user))
+                    .collect();
+            }
+            ServerNotice::Join { user } => {
+                self.presence.insert(user.id.clone(), user);
+            }
+            ServerNotice::Leave { user_id } => {
+                self.presence.remove(&user_id);
+                self.typing_users.remove(&user_id);
+                if self.whois.as_ref() == Some(&user_id) {
+                    self.whois = None;
+                }
+            }
+            ServerNotice::Edit { id, content } => self.transcript.edit(id, content),
+            ServerNotice::Delete { id } => self.transcript.delete(id),
+        }
+        self.clamp_sidebar_selection();
+    }
 
-        // Find the starting message based on scroll position
-        for (i, (line_count, _)) in messages_with_line_counts.iter().enumerate() {
-            if lines_from_top + line_count > self.messages_scroll {
-                start_idx = i;
-                break;
+    fn mark_active(&mut self, user_id: &str) {
+        if let Some(user) = self.presence.get_mut(user_id) {
+            user.last_active_at = time::now_millis();
+        }
+    }
+
+    fn toggle_layout(&mut self) {
+        match self.transcript.style() {
+            LayoutStyle::Bubbles => {
+                self.transcript.set_style(LayoutStyle::Compact);
+                self.notify("📜 Compact layout: one line per message, l or /compact for bubbles");
+            }
+            LayoutStyle::Compact => {
+                self.transcript.set_style(LayoutStyle::Bubbles);
+                self.notify("💬 Bubble layout");
             }
-            lines_from_top += line_count;
         }
+    }
 
-        let visible_messages = self
-            .messages
-            .iter()
-            .skip(start_idx)
-            .take(visible_height)
-            .enumerate()
-            .map(|(_, (m, from_user, author))| {
-                let is_right_aligned = *from_user;
-                let available_width = messages_area.width.saturating_sub(10) as usize;
-                let wrapped_message = textwrap::wrap(m, available_width);
-                let mut list_item_spans = Vec::new();
-
-                // Add a blank line before each message for spacing
-                list_item_spans.push(Line::from(""));
-
-                // Check if this is a system message or history loaded message
-                let is_system_message = author == "system";
-                let is_history_loaded = author == "history_loaded";
-
-                if is_history_loaded {
-                    // History loaded message - display centered dashed line
-                    let line = "--- Recent Messages ---";
-                    let padding = (messages_area.width as usize)
-                        .saturating_sub(line.len())
-                        .saturating_div(2);
-
-                    let mut centered_spans = Vec::new();
-                    centered_spans.push(Span::raw(" ".repeat(padding)));
-                    centered_spans.push(Span::styled(line, Style::default().fg(Color::DarkGray)));
-
-                    list_item_spans.push(Line::from(centered_spans));
-                } else if is_system_message {
-                    // System message - display centered with special styling
-                    for line in wrapped_message {
-                        let line_spans = vec![Span::styled(
-                            format!(" {} ", line),
-                            Style::default()
-                                .bg(Color::Rgb(25, 38, 45))
-                                .fg(Color::Rgb(102, 187, 106)),
-                        )];
-
-                        // Center the system message
-                        let padding = (messages_area.width as usize)
-                            .saturating_sub(line.len() + 2) // +2 for the spaces
-                            .saturating_div(2);
-
-                        let mut centered_spans = Vec::new();
-                        centered_spans.push(Span::raw(" ".repeat(padding)));
-                        centered_spans.extend(line_spans);
-
-                        list_item_spans.push(Line::from(centered_spans));
-                    }
-                } else {
-                    // Regular user message - keep existing formatting
-                    for (line_idx, line) in wrapped_message.iter().enumerate() {
-                        let mut line_spans = Vec::new();
-
-                        if line_idx == 0 {
-                            if is_right_aligned {
-                                let padding = (messages_area.width as usize)
-                                    .saturating_sub(line.len())
-                                    .saturating_sub(10);
-
-                                line_spans.push(Span::raw(" ".repeat(padding)));
-                                line_spans.push(Span::styled(
-                                    format!(" {} ", line),
-                                    Style::default().bg(Color::Rgb(0, 92, 75)).fg(Color::White),
-                                ));
-                                line_spans.push(Span::styled(
-                                    format!(" 🫵 "),
-                                    Style::default().fg(Color::DarkGray),
-                                ));
-                            } else {
-                                line_spans.push(Span::styled(
-                                    format!(" 🤘 "),
-                                    Style::default().fg(Color::DarkGray),
-                                ));
-                                line_spans.push(Span::styled(
-                                    format!(" {} ", line),
-                                    Style::default().bg(Color::Rgb(38, 45, 49)).fg(Color::White),
-                                ));
-                            }
-                        } else {
-                            if is_right_aligned {
-                                let padding = (messages_area.width as usize)
-                                    .saturating_sub(line.len())
-                                    .saturating_sub(6);
-
-                                line_spans.push(Span::raw(" ".repeat(padding)));
-                                line_spans.push(Span::styled(
-                                    format!(" {} ", line),
-                                    Style::default().bg(Color::Rgb(0, 92, 75)).fg(Color::White),
-                                ));
-                            } else {
-                                line_spans.push(Span::raw("    "));
-                                line_spans.push(Span::styled(
-                                    format!(" {} ", line),
-                                    Style::default().bg(Color::Rgb(38, 45, 49)).fg(Color::White),
-                                ));
-                            }
-                        }
+    fn toggle_mouse(&mut self) {
+        let mouse = !self.modes.mouse();
+        match self.modes.set_mouse(mouse) {
+            Ok(()) if mouse => self.notify("🖱️ Mouse on: scroll, click and drag away"),
+            Ok(()) => self.notify("🖱️ Mouse off: select text the usual way, m brings it back"),
+            Err(e) => self.notify(format!("Couldn't switch the mouse: {}", e)),
+        }
+    }
 
-                        list_item_spans.push(Line::from(line_spans));
+    // Wheel scrolls the messages; a click opens a link, selects a message or puts the
+    // input cursor where it landed; the scrollbar can be grabbed and dragged
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        if self.popup_open() {
+            return;
+        }
+        let areas = self.areas.get();
+        let point = Position::new(mouse.column, mouse.row);
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.transcript.scroll_up(WHEEL_LINES),
+            MouseEventKind::ScrollDown => self.transcript.scroll_down(WHEEL_LINES),
+            MouseEventKind::Down(MouseButton::Left) if areas.scrollbar.contains(point) => {
+                self.dragging_scrollbar = true;
+                self.drag_scrollbar(mouse.row);
+            }
+            MouseEventKind::Drag(MouseButton::Left) if self.dragging_scrollbar => {
+                self.drag_scrollbar(mouse.row);
+            }
+            MouseEventKind::Up(MouseButton::Left) => self.dragging_scrollbar = false,
+            MouseEventKind::Down(MouseButton::Left) if areas.unread_badge.contains(point) => {
+                self.transcript.scroll_to_bottom();
+            }
+            MouseEventKind::Down(MouseButton::Left) => {
+                let link = self.link_regions.iter().find(|link| {
+                    link.y == mouse.row && (link.x..link.x + link.width).contains(&mouse.column)
+                });
+                if let Some(url) = link.map(|link| link.url.clone()) {
+                    match links::open(&url) {
+                        Ok(()) => self.notify(format!("🌐 Opening {}", url)),
+                        Err(e) => self.notify(format!("Couldn't open link: {}", e)),
                     }
+                } else if areas.messages.contains(point) {
+                    let clicked = self
+                        .transcript
+                        .message_at((mouse.row - areas.messages.y) as usize);
+                    // Clicking the selected message again lets go of it
+                    if clicked.is_none() || clicked == self.transcript.selected() {
+                        self.stop_selecting();
+                    } else {
+                        self.transcript.select(clicked);
+                        self.input_mode = InputMode::Selecting;
+                    }
+                } else if areas.input.contains(point) {
+                    self.history_search = None;
+                    self.completion = None;
+                    if !self.config.vi_mode {
+                        self.input_mode = InputMode::Editing;
+                    }
+                    self.input.move_to_point(
+                        (mouse.row - areas.input.y) as usize + self.input_scroll.0,
+                        (mouse.column - areas.input.x) as usize + self.input_scroll.1,
+                    );
                 }
+            }
+            _ => {}
+        }
+    }
 
-                // Add a small margin after each message
-                list_item_spans.push(Line::from(""));
+    // Scrolls to the message at the same fraction of the scrollback as `row` is of the
+    // scrollbar track, between its arrows
+    fn drag_scrollbar(&mut self, row: u16) {
+        let track = self.areas.get().scrollbar;
+        let Some(last) = self.transcript.messages().len().checked_sub(1) else {
+            return;
+        };
+        let span = track.height.saturating_sub(3).max(1) as usize;
+        let offset = row.saturating_sub(track.y + 1).min(span as u16) as usize;
+        self.transcript.scroll_to_message(offset * last / span);
+    }
 
-                ListItem::new(list_item_spans)
-            })
-            .collect::<Vec<_>>();
+    fn toggle_sidebar(&mut self) {
+        let shown = !self.screen.sidebar;
+        self.show_sidebar = Some(shown);
+        self.screen.sidebar = shown;
+        self.sidebar_focused = shown;
+        self.clamp_sidebar_selection();
+    }
 
-        // Update the messages list title to show user count
-        let messages_list = List::new(visible_messages)
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(Color::Rgb(69, 90, 100)))
-                    .style(Style::default().bg(Color::Rgb(17, 27, 33)))
-                    .padding(Padding::new(1, 1, 0, 0))
-                    .title(format!(
-                        " 💬 Live Human Specimens Chatting ({} spotted) ",
-                        self.connected_users.lock().unwrap()
-                    ))
-                    .title_style(Style::default().fg(Color::Rgb(0, 230, 118))),
-            )
-            .highlight_style(Style::default().add_modifier(Modifier::BOLD));
+    // Works out the breakpoints for a terminal of `size`; runs before every frame, so a
+    // resize takes effect on the next draw
+    fn fit(&mut self, size: Size) {
+        let sidebar = self
+            .show_sidebar
+            .unwrap_or(self.config.auto_sidebar && size.width >= WIDE_WIDTH);
+        self.screen = Screen {
+            short_header: size.height < SHORT_HEIGHT,
+            help_line: size.height >= TINY_HEIGHT,
+            sidebar,
+            input_rows: (self.config.input_height as usize).min((size.height / 4).max(1) as usize),
+        };
+        if !sidebar {
+            self.sidebar_focused = false;
+        }
+    }
 
-        frame.render_widget(messages_list, messages_area);
+    fn clamp_sidebar_selection(&mut self) {
+        let selected = match self.presence.len() {
+            0 => None,
+            len => Some(self.sidebar_state.selected().unwrap_or(0).min(len - 1)),
+        };
+        self.sidebar_state.select(selected);
+    }
 
-        frame.render_stateful_widget(
-            Scrollbar::new(ScrollbarOrientation::VerticalRight)
-                .thumb_style(Style::default().fg(Color::Rgb(0, 150, 136)))
-                .track_style(Style::default().fg(Color::Rgb(38, 45, 49))),
-            messages_area.inner(Margin {
-                vertical: 1,
-                horizontal: 0,
-            }),
-            &mut messages_scroll_state,
-        );
+    fn select_previous_user(&mut self) {
+        self.sidebar_state.select_previous();
+        self.clamp_sidebar_selection();
+    }
 
-        let input = Paragraph::new(self.input.as_str())
-            .style(match self.input_mode {
-                InputMode::Normal => Style::default().fg(Color::Gray),
-                InputMode::Editing => Style::default().fg(Color::White),
-            })
-            .block(
-                Block::default()
-                    .borders(Borders::ALL)
-                    .border_type(BorderType::Rounded)
-                    .border_style(Style::default().fg(Color::Rgb(69, 90, 100)))
-                    .style(Style::default().bg(Color::Rgb(17, 27, 33)))
-                    .title(" 📝 Drop Your Message Here 📝 ")
-                    .title_style(Style::default().fg(Color::Rgb(0, 230, 118))),
-            )
-            .wrap(Wrap { trim: true });
-        frame.render_widget(input, input_area);
+    fn select_next_user(&mut self) {
+        self.sidebar_state.select_next();
+        self.clamp_sidebar_selection();
+    }
 
-        let (msg, style) = match self.input_mode {
-            InputMode::Normal => (
-                vec![
-                    "Smash ".into(),
-                    "Enter".bold(),
-                    " to type, ".into(),
-                    "Up/Down".bold(),
-                    " to scroll, ".into(),
-                    "q".bold(),
-                    " to rage quit".into(),
-                ],
-                Style::default().fg(Color::Gray).bg(Color::Rgb(17, 27, 33)),
-            ),
-            InputMode::Editing => (
-                vec![
-                    "Hit ".into(),
-                    "Esc".bold(),
-                    " to stop, ".into(),
-                    "Up/Down".bold(),
-                    " to scroll, ".into(),
-                    "Enter".bold(),
-                    " to unleash".into(),
-                ],
-                Style::default().fg(Color::Gray),
+    fn open_whois(&mut self) {
+        self.whois = self
+            .sidebar_state
+            .selected()
+            .and_then(|index| self.presence.keys().nth(index))
+            .cloned();
+    }
+
+    fn notify(&mut self, text: impl Into<String>) {
+        self.notice = Some((text.into(), Instant::now()));
+    }
+
+    // Copies the last code block of the message at the scroll position, or the nearest one above
+    fn copy_code_block(&mut self) {
+        let end = self.transcript.focused().map_or(0, |index| index + 1);
+        let block = self.transcript.messages()[..end]
+            .iter()
+            .rev()
+            .filter(|message| message.is_chat())
+            .find_map(|message| markdown::code_blocks(&message.text).pop());
+
+        match block {
+            Some(block) => self.copy_to_clipboard(
+                &block.code,
+                &format!(
+                    "📋 Copied {} block ({} lines)",
+                    block.lang.as_deref().unwrap_or("code"),
+                    block.code.lines().count()
+                ),
             ),
-        };
-        let text = Text::from(Line::from(msg)).patch_style(style);
-        let help_message = Paragraph::new(text).style(Style::default().bg(Color::Rgb(17, 27, 33)));
-        frame.render_widget(help_message, help_area);
+            None => self.notify("No code block to copy"),
+        }
+    }
 
-        match self.input_mode {
-            InputMode::Normal => {}
-            InputMode::Editing => frame.set_cursor_position(Position::new(
-                input_area.x + self.character_index as u16 + 1,
-                input_area.y + 1,
-            )),
+    // Copies the text of the selected message, or the one at the bottom of the view
+    fn copy_message(&mut self) {
+        match self.transcript.focused() {
+            Some(index) if !self.transcript.messages()[index].deleted => {
+                let text = self.transcript.messages()[index].text.clone();
+                self.copy_to_clipboard(&text, "📋 Copied message");
+            }
+            _ => self.notify("No message to copy"),
+        }
+    }
+
+    // Copies everything in view, without the bubbles and gutters a terminal selection picks up
+    fn copy_screen(&mut self) {
+        let text = self.transcript.shown_text();
+        if text.is_empty() {
+            self.notify("Nothing on screen to copy");
+        } else {
+            let count = text.lines().count();
+            self.copy_to_clipboard(&text, &format!("📋 Copied the screen ({} lines)", count));
         }
     }
+
+    // Lists the links of the message at the scroll position, or the nearest one above
+    fn open_url_picker(&mut self) {
+        let end = self.transcript.focused().map_or(0, |index| index + 1);
+        let urls = self.transcript.messages()[..end]
+            .iter()
+            .rev()
+            .filter(|message| message.is_chat())
+            .map(|message| markdown::urls(&message.text))
+            .find(|urls| !urls.is_empty());
+
+        match urls {
+            Some(urls) => {
+                self.url_picker = Some((urls, ListState::default().with_selected(Some(0))))
+            }
+            None => self.notify("No links to open"),
+        }
+    }
+
+    fn open_picked_url(&mut self, index: Option<usize>) {
+        let Some((urls, state)) = self.url_picker.take() else {
+            return;
+        };
+        let Some(url) = index.or(state.selected()).and_then(|index| urls.get(index)) else {
+            self.url_picker = Some((urls, state));
+            return;
+        };
+        match links::open(url) {
+            Ok(()) => self.notify(format!("🌐 Opening {}", url)),
+            Err(e) => self.notify(format!("Couldn't open link: {}", e)),
+        }
+    }
+
+    // Does what a key in the keymap is bound to; Break means quit
+    fn run_action(&mut self, action: Action) -> ControlFlow<()> {
+        match action {
+            Action::Quit => return ControlFlow::Break(()),
+            Action::Help => self.show_keys = true,
+            Action::Insert => self.input_mode = InputMode::Editing,
+            Action::ScrollUp => self.transcript.scroll_up(1),
+            Action::ScrollDown => self.transcript.scroll_down(1),
+            Action::PageUp => self.transcript.scroll_up(self.transcript.page_height()),
+            Action::PageDown => self.transcript.scroll_down(self.transcript.page_height()),
+            Action::Latest => self.transcript.scroll_to_bottom(),
+            Action::Select => self.start_selecting(),
+            Action::Sidebar => self.toggle_sidebar(),
+            Action::FocusSidebar => self.sidebar_focused = self.screen.sidebar,
+            Action::CopyCode => self.copy_code_block(),
+            Action::CopyScreen => self.copy_screen(),
+            Action::Links => self.open_url_picker(),
+            Action::Mouse => self.toggle_mouse(),
+            Action::Layout => self.toggle_layout(),
+            Action::Send => return self.submit_message(),
+            // Leaving while editing a sent message gives up on the edit
+            Action::Leave if self.editing.is_some() => {
+                self.stop_typing();
+                self.finish_editing();
+            }
+            Action::Leave => {
+                self.stop_typing();
+                self.input_mode = InputMode::Normal;
+                if self.config.vi_mode {
+                    vi::leave_insert(&mut self.input);
+                }
+            }
+            Action::SearchHistory => self.start_history_search(),
+        }
+        ControlFlow::Continue(())
+    }
+
+    // Puts a cursor on the message at the scroll position so j/k can move it
+    fn start_selecting(&mut self) {
+        if self.transcript.messages().is_empty() {
+            self.notify("No messages to select");
+            return;
+        }
+        if self.transcript.selected().is_none() {
+            self.transcript.select(self.transcript.focused());
+        }
+        self.input_mode = InputMode::Selecting;
+    }
+
+    fn stop_selecting(&mut self) {
+        self.transcript.select(None);
+        self.input_mode = InputMode::Normal;
+    }
+
+    fn handle_selecting_key(&mut self, key: event::KeyEvent) {
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => self.transcript.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.transcript.select_next(),
+            KeyCode::Home | KeyCode::Char('g') => self.select_message(0),
+            KeyCode::End | KeyCode::Char('G') => {
+                self.select_message(self.transcript.messages().len().saturating_sub(1))
+            }
+            KeyCode::Enter | KeyCode::Char(' ') => self.open_message_menu(),
+            KeyCode::Char('?') | KeyCode::F(1) => self.show_keys = true,
+            KeyCode::Char('c') => self.copy_code_block(),
+            KeyCode::Esc | KeyCode::Char('q') => self.stop_selecting(),
+            KeyCode::Char(c) => {
+                let Some(index) = self.transcript.selected() else {
+                    return;
+                };
+                if let Some(action) = self
+                    .message_actions(index)
+                    .into_iter()
+                    .find(|action| action.key() == c)
+                {
+                    self.run_message_action(index, action);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn select_message(&mut self, index: usize) {
+        self.transcript.select(Some(index));
+        self.transcript.reveal(index);
+    }
+
+    fn message_actions(&self, index: usize) -> Vec<MessageAction> {
+        let message = &self.transcript.messages()[index];
+        MessageAction::available(message, !markdown::urls(&message.text).is_empty())
+    }
+
+    fn open_message_menu(&mut self) {
+        if let Some(index) = self.transcript.selected() {
+            let actions = self.message_actions(index);
+            self.message_menu = Some(MessageMenu::new(index, MenuItems::Actions(actions)));
+        }
+    }
+
+    // Enter or a shortcut in the open menu
+    fn pick_menu_item(&mut self, picked: Option<usize>) {
+        let Some(menu) = self.message_menu.take() else {
+            return;
+        };
+        let Some(item) = picked
+            .or(menu.state.selected())
+            .filter(|&item| item < menu.len())
+        else {
+            self.message_menu = Some(menu);
+            return;
+        };
+        match menu.items {
+            MenuItems::Actions(actions) => self.run_message_action(menu.index, actions[item]),
+            MenuItems::Reactions => self.react(menu.index, actions::REACTIONS[item]),
+            MenuItems::ConfirmDelete if item == 0 => self.delete_message(menu.index),
+            MenuItems::ConfirmDelete => {}
+        }
+    }
+
+    fn run_message_action(&mut self, index: usize, action: MessageAction) {
+        self.message_menu = None;
+        let message = &self.transcript.messages()[index];
+        match action {
+            MessageAction::Reply => {
+                let reply = format!(
+                    "{}\n\n@{} ",
+                    actions::quote(&actions::excerpt(&message.text)),
+                    message.author
+                );
+                self.stop_selecting();
+                self.input.replace_range(0, 0, &reply);
+                self.input_mode = InputMode::Editing;
+            }
+            MessageAction::React => {
+                self.message_menu = Some(MessageMenu::new(index, MenuItems::Reactions));
+            }
+            MessageAction::Copy => {
+                let text = message.text.clone();
+                self.copy_to_clipboard(&text, "📋 Copied message");
+            }
+            MessageAction::CopyQuote => {
+                let text = format!("{}\n> — {}", actions::quote(&message.text), message.author);
+                self.copy_to_clipboard(&text, "📋 Copied as a quote");
+            }
+            MessageAction::OpenLinks => {
+                let urls = markdown::urls(&message.text);
+                self.url_picker = Some((urls, ListState::default().with_selected(Some(0))));
+            }
+            MessageAction::Details => self.details = Some(index),
+            MessageAction::Edit => {
+                let Some(id) = message.server_id else {
+                    return;
+                };
+                let text = message.text.clone();
+                let draft = self.input.text().to_string();
+                self.stop_selecting();
+                self.input.set_text(&text, text.len());
+                self.editing = Some((id, draft));
+                self.input_mode = InputMode::Editing;
+            }
+            MessageAction::Delete => {
+                self.message_menu = Some(MessageMenu::new(index, MenuItems::ConfirmDelete));
+            }
+        }
+    }
+
+    // Sends `emoji` as a short message quoting what it's reacting to
+    fn react(&mut self, index: usize, emoji: &str) {
+        let message = &self.transcript.messages()[index];
+        let first_line = message.text.lines().next().unwrap_or_default();
+        let mut reaction = format!("{}\n\n", actions::quote(first_line));
+        if !message.from_user {
+            reaction.push_str(&format!("@{} ", message.author));
+        }
+        reaction.push_str(emoji);
+        if self.send_chat(reaction) {
+            if let Some(emoji) = emojis::get(emoji) {
+                self.recent_emoji.record(emoji);
+            }
+            self.stop_selecting();
+        }
+    }
+
+    fn delete_message(&mut self, index: usize) {
+        if let Some(id) = self.transcript.messages()[index].server_id
+            && self.send(ClientEvent::Delete(id))
+        {
+            self.stop_selecting();
+        }
+    }
+
+    fn copy_to_clipboard(&mut self, text: &str, done: &str) {
+        match clipboard::copy(text, self.config.clipboard_fallback) {
+            Ok(()) => self.notify(done),
+            Err(e) => self.notify(format!("Copy failed: {}", e)),
+        }
+    }
+
+    // Puts back the draft that editing a message pushed aside
+    fn finish_editing(&mut self) {
+        if let Some((_, draft)) = self.editing.take() {
+            self.input.set_text(&draft, draft.len());
+        }
+    }
+
+    fn run(&mut self, mut terminal: DefaultTerminal, _rt: tokio::runtime::Runtime) -> Result<()> {
+        // Remove the EventStream line that's causing the error
+        // let mut event_reader = event::EventStream::new();
+
+        loop {
+            // Drain everything the WebSocket sent, so chat doesn't queue behind typing
+            // and presence updates or the history burst on connect
+            while let Ok(event) = self.msg_rx.try_recv() {
+                match event {
+                    ServerEvent::Chat(msg) => {
+                        // A sent message means they're done typing it
+                        self.typing_users.remove(&msg.author_id);
+                        self.mark_active(&msg.author_id);
+                        let is_from_user = msg.author_id == self.user_id;
+                        self.transcript.push(
+                            msg.content,
+                            msg.author_id,
+                            is_from_user,
+                            msg.timestamp,
+                            msg.id,
+                        );
+                    }
+                    ServerEvent::Notice(notice) => self.handle_notice(notice),
+                    ServerEvent::Error { error } => self.notify(format!("Server says: {}", error)),
+                }
+            }
+            self.expire_typing();
+
+            let size = terminal.size()?;
+            self.fit(size);
+            self.follow_input_cursor(size.width.saturating_sub(2) as usize);
+
+            let mut link_regions = Vec::new();
+            let completed = terminal.draw(|frame| link_regions = self.draw(frame))?;
+            // Only rewrite hyperlinks when they moved or something was drawn over them;
+            // ratatui leaves unchanged cells alone
+            let link_cells = links::cells_under(completed.buffer, &link_regions);
+            if link_regions != self.link_regions
+                || completed.area != self.link_area
+                || link_cells != self.link_cells
+            {
+                self.link_area = completed.area;
+                let buffer = completed.buffer.clone();
+                links::emit_hyperlinks(terminal.backend_mut(), &buffer, &link_regions)?;
+                self.link_regions = link_regions;
+                self.link_cells = link_cells;
+            }
+
+            // Use poll with a timeout to make the UI responsive without blocking
+            if event::poll(std::time::Duration::from_millis(100))? {
+                let key = match event::read()? {
+                    Event::Key(key) => key,
+                    Event::Paste(text) => {
+                        self.handle_paste(text);
+                        continue;
+                    }
+                    Event::Mouse(mouse) => {
+                        self.handle_mouse(mouse);
+                        continue;
+                    }
+                    _ => continue,
+                };
+
+                if self.whois.is_some() || self.details.is_some() || self.show_keys {
+                    // Any key dismisses the whois or details card, or the key overlay
+                    if key.kind == KeyEventKind::Press {
+                        self.whois = None;
+                        self.details = None;
+                        self.show_keys = false;
+                    }
+                    continue;
+                }
+
+                if let Some(menu) = self.message_menu.as_mut() {
+                    if key.kind == KeyEventKind::Press {
+                        match key.code {
+                            KeyCode::Up | KeyCode::Char('k') => menu.state.select_previous(),
+                            KeyCode::Down | KeyCode::Char('j') => menu.select_next(),
+                            KeyCode::Enter => self.pick_menu_item(None),
+                            KeyCode::Esc | KeyCode::Char('q') => self.message_menu = None,
+                            KeyCode::Char(c) => {
+                                let picked = match &menu.items {
+                                    MenuItems::Actions(actions) => {
+                                        actions.iter().position(|action| action.key() == c)
+                                    }
+                                    MenuItems::Reactions => c
+                                        .to_digit(10)
+                                        .and_then(|digit| (digit as usize).checked_sub(1)),
+                                    MenuItems::ConfirmDelete => match c {
+                                        'y' => Some(0),
+                                        'n' => Some(1),
+                                        _ => None,
+                                    },
+                                };
+                                if picked.is_some() {
+                                    self.pick_menu_item(picked);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+
+                if let Some((_, state)) = self.paste_prompt.as_mut() {
+                    if key.kind == KeyEventKind::Press {
+                        match key.code {
+                            KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                            KeyCode::Down | KeyCode::Char('j') => {
+                                state.select(
+                                    state
+                                        .selected()
+                                        .map(|i| (i + 1).min(PASTE_ACTIONS.len() - 1)),
+                                );
+                            }
+                            KeyCode::Enter | KeyCode::Char('1'..='9') => {
+                                let index = match key.code {
+                                    KeyCode::Char(c) => Some(c as usize - '1' as usize),
+                                    _ => None,
+                                };
+                                if self.finish_paste(index).is_break() {
+                                    return Ok(());
+                                }
+                            }
+                            KeyCode::Esc | KeyCode::Char('q') => self.paste_prompt = None,
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+
+                if let Some((urls, state)) = self.url_picker.as_mut() {
+                    if key.kind == KeyEventKind::Press {
+                        match key.code {
+                            KeyCode::Up | KeyCode::Char('k') => state.select_previous(),
+                            KeyCode::Down | KeyCode::Char('j') => {
+                                state.select(state.selected().map(|i| (i + 1).min(urls.len() - 1)));
+                            }
+                            KeyCode::Enter => self.open_picked_url(None),
+                            KeyCode::Char(c @ '1'..='9') => {
+                                self.open_picked_url(Some(c as usize - '1' as usize));
+                            }
+                            KeyCode::Esc | KeyCode::Char('q') => self.url_picker = None,
+                            _ => {}
+                        }
+                    }
+                    continue;
+                }
+
+                match self.input_mode {
+                    // Releases would count as presses, flipping toggles straight back and
+                    // repeating vi counts and operators
+                    InputMode::Normal if key.kind != KeyEventKind::Press => {}
+                    InputMode::Normal if self.sidebar_focused => match key.code {
+                        KeyCode::Up | KeyCode::Char('k') => self.select_previous_user(),
+                        KeyCode::Down | KeyCode::Char('j') => self.select_next_user(),
+                        KeyCode::Enter => self.open_whois(),
+                        KeyCode::Tab | KeyCode::Esc => self.sidebar_focused = false,
+                        // Quit, help and closing the sidebar keep working from in here
+                        _ => {
+                            if let Some(action @ (Action::Quit | Action::Help | Action::Sidebar)) =
+                                self.config.keymap.action(keymap::Mode::Normal, key)
+                                && self.run_action(action).is_break()
+                            {
+                                return Ok(());
+                            }
+                        }
+                    },
+                    InputMode::Normal if self.config.vi_mode => {
+                        match self.vi.handle_key(&mut self.input, key) {
+                            vi::Action::None => {}
+                            vi::Action::Insert => self.input_mode = InputMode::Editing,
+                            vi::Action::ScrollUp(count) => self.transcript.scroll_up(count),
+                            vi::Action::ScrollDown(count) => self.transcript.scroll_down(count),
+                            vi::Action::HalfPageUp => {
+                                self.transcript.scroll_up(self.transcript.page_height() / 2)
+                            }
+                            vi::Action::HalfPageDown => self
+                                .transcript
+                                .scroll_down(self.transcript.page_height() / 2),
+                            vi::Action::Top => self.transcript.scroll_to_top(),
+                            vi::Action::Bottom => self.transcript.scroll_to_bottom(),
+                            vi::Action::Submit => {
+                                if self.submit_message().is_break() {
+                                    return Ok(());
+                                }
+                            }
+                            // Keys vi doesn't use go to the normal-mode keymap; the rest, like
+                            // c, o and u, have /copy, /links and /users standing in
+                            vi::Action::Unhandled => {
+                                if let Some(action) =
+                                    self.config.keymap.action(keymap::Mode::Normal, key)
+                                    && self.run_action(action).is_break()
+                                {
+                                    return Ok(());
+                                }
+                            }
+                        }
+                    }
+                    InputMode::Normal => {
+                        if let Some(action) = self.config.keymap.action(keymap::Mode::Normal, key)
+                            && self.run_action(action).is_break()
+                        {
+                            return Ok(());
+                        }
+                    }
+                    InputMode::Selecting if key.kind == KeyEventKind::Press => {
+                        self.handle_selecting_key(key)
+                    }
+                    InputMode::Selecting => {}
+                    InputMode::Editing
+                        if key.kind == KeyEventKind::Press && self.ctrl_x_pending =>
+                    {
+                        self.ctrl_x_pending = false;
+                        self.notice = None;
+                        if key.code == KeyCode::Char('e')
+                            && key.modifiers.contains(KeyModifiers::CONTROL)
+                        {
+                            self.compose_in_editor(&mut terminal)?;
+                        }
+                    }
+                    InputMode::Editing
+                        if key.kind == KeyEventKind::Press && self.history_search.is_some() =>
+                    {
+                        self.handle_history_search_key(key)
+                    }
+                    InputMode::Editing
+                        if key.kind == KeyEventKind::Press
+                            && matches!(key.code, KeyCode::Tab | KeyCode::BackTab) =>
+                    {
+                        self.complete(key.code == KeyCode::BackTab)
+                    }
+                    InputMode::Editing
+                        if key.kind == KeyEventKind::Press
+                            && key.code == KeyCode::Esc
+                            && self.completion.is_some() =>
+                    {
+                        self.cancel_completion()
+                    }
+                    InputMode::Editing
+                        if key.kind == KeyEventKind::Press
+                            && self.emoji_picker.is_some()
+                            && matches!(
+                                key.code,
+                                KeyCode::Up | KeyCode::Down | KeyCode::Esc | KeyCode::Enter
+                            ) =>
+                    {
+                        let picker = self.emoji_picker.as_mut().unwrap();
+                        match key.code {
+                            KeyCode::Up => picker.select_previous(),
+                            KeyCode::Down => picker.select_next(),
+                            KeyCode::Esc => {
+                                self.emoji_dismissed_at = Some(picker.start);
+                                self.emoji_picker = None;
+                            }
+                            _ => self.accept_emoji(),
+                        }
+                    }
+                    InputMode::Editing
+                        if key.kind == KeyEventKind::Press
+                            && let Some(action) =
+                                self.config.keymap.action(keymap::Mode::Editing, key) =>
+                    {
+                        if self.run_action(action).is_break() {
+                            return Ok(());
+                        }
+                    }
+                    InputMode::Editing if key.kind == KeyEventKind::Press => match key.code {
+                        // Up/Down recall history on an empty or recalled input, move between
+                        // input lines otherwise, and scroll once both run out
+                        KeyCode::Up => {
+                            if !self.recall_previous()
+                                && self.input.handle_key(key) == Outcome::Ignored
+                            {
+                                self.transcript.scroll_up(1);
+                            }
+                        }
+                        KeyCode::Down => {
+                            if !self.recall_next() && self.input.handle_key(key) == Outcome::Ignored
+                            {
+                                self.transcript.scroll_down(1);
+                            }
+                        }
+                        KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            if !self.recall_previous() {
+                                self.input.move_up();
+                            }
+                        }
+                        KeyCode::Char('n') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            if !self.recall_next() {
+                                self.input.move_down();
+                            }
+                        }
+                        KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.ctrl_x_pending = true;
+                            self.notify("Ctrl-X…");
+                        }
+                        _ => {
+                            if self.input.handle_key(key) == Outcome::Edited {
+                                self.notify_typing();
+                            }
+                        }
+                    },
+                    InputMode::Editing => {}
+                }
+
+                // Any other key settles on the candidate Tab put in
+                if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+                    self.completion = None;
+                }
+                if matches!(self.input_mode, InputMode::Editing) && self.history_search.is_none() {
+                    self.update_emoji_picker();
+                } else {
+                    self.emoji_picker = None;
+                }
+            }
+        }
+    }
+
+    // Rows the input box shows, growing with the text up to the configured height
+    fn input_rows(&self) -> usize {
+        self.input.line_count().min(self.screen.input_rows)
+    }
+
+    // Scrolls the input just far enough to keep the cursor in view, given the
+    // box's inner width
+    fn follow_input_cursor(&mut self, width: usize) {
+        let (line, column) = self.input.cursor_position();
+        let rows = self.input_rows();
+        let (top, left) = self.input_scroll;
+        self.input_scroll = (
+            top.clamp(line.saturating_sub(rows - 1), line)
+                .min(self.input.line_count() - rows),
+            // Leave a cell for the cursor itself past the last character
+            left.clamp(column.saturating_sub(width.max(1) - 1), column),
+        );
+    }
+
+    // Character count for the input border, warning as it nears the soft limit
+    fn input_counter(&self) -> Line<'static> {
+        let count = self.input.grapheme_count();
+        let limit = self.config.message_limit;
+        let (text, color) = if count > limit {
+            (
+                format!(" ⚠ {}/{} – that's a lot ", count, limit),
+                self.theme.error,
+            )
+        } else if count * 10 >= limit * 9 {
+            (format!(" {}/{} ", count, limit), self.theme.warning)
+        } else {
+            (format!(" {}/{} ", count, limit), self.theme.muted)
+        };
+        Line::styled(text, Style::default().fg(color)).right_aligned()
+    }
+
+    // Draws the whole UI, returning where the visible hyperlinks ended up
+    fn draw(&self, frame: &mut Frame) -> Vec<LinkRegion> {
+        let typing_indicator = self.typing_indicator();
+        let vertical = Layout::vertical([
+            Constraint::Length(if self.screen.short_header { 1 } else { 5 }),
+            Constraint::Min(1),
+            Constraint::Length(typing_indicator.is_some() as u16),
+            Constraint::Length(self.input_rows() as u16 + 2),
+            Constraint::Length(self.screen.help_line as u16),
+        ]);
+        let [
+            title_area,
+            messages_area,
+            typing_area,
+            input_area,
+            help_area,
+        ] = vertical.areas(frame.area());
+        let (messages_area, sidebar_area) = if self.screen.sidebar {
+            let [messages_area, sidebar_area] =
+                Layout::horizontal([Constraint::Min(1), Constraint::Length(SIDEBAR_WIDTH)])
+                    .areas(messages_area);
+            (messages_area, Some(sidebar_area))
+        } else {
+            (messages_area, None)
+        };
+
+        let bg_block = Block::default().style(Style::default().bg(self.theme.background));
+        frame.render_widget(bg_block, frame.area());
+
+        // Get connection status
+        let status = self.connection_status.lock().unwrap().clone();
+
+        let notice = self
+            .notice
+            .as_ref()
+            .filter(|(_, shown)| shown.elapsed() < NOTICE_DURATION)
+            .map(|(notice, _)| notice.as_str());
+
+        // Title, status and user ID get a line each in a bordered box. Short terminals get
+        // them all on one line, which also carries notices once the help line is gone.
+        if self.screen.short_header {
+            let accent = Style::default().fg(self.theme.accent);
+            let line = match notice {
+                Some(notice) if !self.screen.help_line => Line::styled(notice, accent),
+                _ => Line::from(vec![
+                    Span::styled("💬 Global Chat", accent.add_modifier(Modifier::BOLD)),
+                    Span::styled(" · ", Style::default().fg(self.theme.faint)),
+                    Span::styled(&status, accent),
+                    Span::styled(" · ", Style::default().fg(self.theme.faint)),
+                    Span::styled(&self.user_id, accent),
+                ]),
+            };
+            let title = Paragraph::new(line)
+                .style(Style::default().bg(self.theme.surface))
+                .alignment(ratatui::layout::Alignment::Center);
+            frame.render_widget(title, title_area);
+        } else {
+            let title_text = vec![
+                Line::from(vec![Span::styled(
+                    "💬 Global Chat 💬",
+                    Style::default()
+                        .fg(self.theme.accent)
+                        .add_modifier(Modifier::BOLD),
+                )]),
+                Line::from(vec![
+                    Span::styled("Status: ", Style::default().fg(self.theme.text)),
+                    Span::styled(&status, Style::default().fg(self.theme.accent)),
+                ]),
+                Line::from(vec![
+                    Span::styled("Your ID: ", Style::default().fg(self.theme.text)),
+                    Span::styled(&self.user_id, Style::default().fg(self.theme.accent)),
+                ]),
+            ];
+
+            let title = Paragraph::new(title_text)
+                .style(Style::default().bg(self.theme.surface))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .border_type(BorderType::Rounded)
+                        .border_style(Style::default().fg(self.theme.border)),
+                )
+                .alignment(ratatui::layout::Alignment::Center);
+            frame.render_widget(title, title_area);
+        }
+
+        // Rows inside the border and the one-column padding on each side
+        let messages_inner = messages_area.inner(Margin {
+            vertical: 1,
+            horizontal: 2,
+        });
+        let scrollbar_area = messages_area.inner(Margin {
+            vertical: 1,
+            horizontal: 0,
+        });
+        // Sits at the right end of the bottom border, clear of the corner
+        let unread_badge = match self.transcript.unread() {
+            0 => None,
+            unread => Some(format!(" ↓ {} new ", unread)),
+        };
+        let badge_width = unread_badge
+            .as_deref()
+            .map_or(0, |badge| badge.width() as u16);
+        self.areas.set(Areas {
+            messages: messages_inner,
+            scrollbar: Rect {
+                x: scrollbar_area.right().saturating_sub(1),
+                width: scrollbar_area.width.min(1),
+                ..scrollbar_area
+            },
+            input: input_area.inner(Margin::new(1, 1)),
+            unread_badge: Rect {
+                x: messages_area.right().saturating_sub(badge_width + 1),
+                y: messages_area.bottom().saturating_sub(1),
+                width: badge_width,
+                height: (badge_width > 0) as u16,
+            },
+        });
+        let rows = self.transcript.visible(
+            messages_inner.width as usize,
+            messages_inner.height as usize,
+        );
+        let mut link_regions = Vec::new();
+        for (y, row) in (messages_inner.y..).zip(&rows) {
+            link_regions.extend(row.links.iter().map(|link| LinkRegion {
+                x: messages_inner.x + link.column as u16,
+                y,
+                width: link.width as u16,
+                url: link.url.clone(),
+            }));
+        }
+
+        let mut messages_block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Rounded)
+            .border_style(Style::default().fg(self.theme.border))
+            .style(Style::default().bg(self.theme.surface))
+            .padding(Padding::new(1, 1, 0, 0))
+            .title(format!(
+                " 💬 Live Human Specimens Chatting ({} spotted) ",
+                self.presence.len().max(1)
+            ))
+            .title_style(Style::default().fg(self.theme.accent));
+        if let Some(badge) = unread_badge {
+            messages_block = messages_block.title_bottom(
+                Line::from(Span::styled(
+                    badge,
+                    Style::default()
+                        .fg(self.theme.surface)
+                        .bg(self.theme.accent)
+                        .add_modifier(Modifier::BOLD),
+                ))
+                .right_aligned(),
+            );
+        }
+        let messages_list =
+            Paragraph::new(rows.into_iter().map(|row| row.line).collect::<Vec<_>>())
+                .block(messages_block);
+
+        frame.render_widget(messages_list, messages_area);
+
+        frame.render_stateful_widget(
+            Scrollbar::new(ScrollbarOrientation::VerticalRight)
+                .thumb_style(Style::default().fg(self.theme.scrollbar))
+                .track_style(Style::default().fg(self.theme.highlight)),
+            scrollbar_area,
+            &mut self.transcript.scrollbar(),
+        );
+
+        if let Some(typing) = typing_indicator {
+            let typing_line = Paragraph::new(Line::from(Span::styled(
+                format!(" ✍️ {}", typing),
+                Style::default()
+                    .fg(self.theme.system_text)
+                    .add_modifier(Modifier::ITALIC),
+            )));
+            frame.render_widget(typing_line, typing_area);
+        }
+
+        if let Some(sidebar_area) = sidebar_area {
+            self.draw_sidebar(frame, sidebar_area);
+        }
+
+        let title = match &self.history_search {
+            Some(search) if !search.query.is_empty() && search.found.is_none() => {
+                format!(" 🔎 failing reverse-i-search: {} ", search.query)
+            }
+            Some(search) => format!(" 🔎 reverse-i-search: {} ", search.query),
+            None if self.editing.is_some() => {
+                " ✏️ Editing your message – Esc to cancel ".to_string()
+            }
+            None => " 📝 Drop Your Message Here 📝 ".to_string(),
+        };
+        let input_text = match self.vi.selection(&self.input) {
+            Some(selection) if self.config.vi_mode => highlight_text(
+                self.input.text(),
+                selection,
+                Style::default().bg(self.theme.border),
+            ),
+            _ => Text::from(self.input.text()),
+        };
+        // Which vi mode the input is in, when vi mode is on
+        let vi_tag = match (self.config.vi_mode, &self.input_mode) {
+            (false, _) => Line::default(),
+            (true, InputMode::Editing) => Line::from(" INSERT ").centered(),
+            (true, InputMode::Normal) if self.vi.is_visual() => Line::from(" VISUAL ").centered(),
+            (true, InputMode::Normal) => Line::from(" NORMAL ").centered(),
+            (true, InputMode::Selecting) => Line::from(" SELECT ").centered(),
+        };
+        let input = Paragraph::new(input_text)
+            .style(match self.input_mode {
+                InputMode::Normal | InputMode::Selecting => Style::default().fg(self.theme.muted),
+                InputMode::Editing => Style::default().fg(self.theme.text),
+            })
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.border))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(title)
+                    .title(vi_tag)
+                    .title(self.input_counter())
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .scroll((self.input_scroll.0 as u16, self.input_scroll.1 as u16));
+        frame.render_widget(input, input_area);
+
+        // Whatever the keymap has these bound to
+        let normal_key =
+            |action| Span::raw(self.config.keymap.key(keymap::Mode::Normal, action)).bold();
+        let editing_key =
+            |action| Span::raw(self.config.keymap.key(keymap::Mode::Editing, action)).bold();
+        let (msg, style) = match self.input_mode {
+            InputMode::Normal if self.config.vi_mode => (
+                vec![
+                    "i/a/A/o".bold(),
+                    " to insert, ".into(),
+                    "w/b/e/0/$".bold(),
+                    " to move, ".into(),
+                    "d/c/y".bold(),
+                    " to edit, ".into(),
+                    "v".bold(),
+                    " to select, ".into(),
+                    "j/k/gg/G".bold(),
+                    " to scroll, ".into(),
+                    normal_key(Action::Select),
+                    " to pick a message, ".into(),
+                    "Enter".bold(),
+                    " to send, ".into(),
+                    normal_key(Action::Help),
+                    " for all keys".into(),
+                ],
+                Style::default().fg(self.theme.muted).bg(self.theme.surface),
+            ),
+            InputMode::Normal => (
+                vec![
+                    "Smash ".into(),
+                    normal_key(Action::Insert),
+                    " to type, ".into(),
+                    normal_key(Action::ScrollUp),
+                    "/".bold(),
+                    normal_key(Action::ScrollDown),
+                    " to scroll, ".into(),
+                    normal_key(Action::Help),
+                    " for keys, ".into(),
+                    normal_key(Action::Sidebar),
+                    " for humans, ".into(),
+                    normal_key(Action::Select),
+                    " to pick a message, ".into(),
+                    normal_key(Action::Quit),
+                    " to rage quit".into(),
+                ],
+                Style::default().fg(self.theme.muted).bg(self.theme.surface),
+            ),
+            InputMode::Selecting => (
+                vec![
+                    "j/k".bold(),
+                    " to move, ".into(),
+                    "Enter".bold(),
+                    " for actions, ".into(),
+                    "r".bold(),
+                    " reply, ".into(),
+                    "+".bold(),
+                    " react, ".into(),
+                    "y/Y/c".bold(),
+                    " copy text/quote/code, ".into(),
+                    "i".bold(),
+                    " details, ".into(),
+                    "e/d".bold(),
+                    " edit/delete yours, ".into(),
+                    "Esc".bold(),
+                    " to stop".into(),
+                ],
+                Style::default().fg(self.theme.muted).bg(self.theme.surface),
+            ),
+            InputMode::Editing => (
+                vec![
+                    "Hit ".into(),
+                    editing_key(Action::Leave),
+                    " to stop, ".into(),
+                    editing_key(Action::Send),
+                    " to unleash, ".into(),
+                    "Shift+Enter".bold(),
+                    " for a new line, ".into(),
+                    editing_key(Action::SearchHistory),
+                    " to dig up old ones, ".into(),
+                    editing_key(Action::Help),
+                    " for keys".into(),
+                ],
+                Style::default().fg(self.theme.muted),
+            ),
+        };
+        let text = match notice {
+            Some(notice) => Text::from(Line::from(notice)).patch_style(
+                Style::default()
+                    .fg(self.theme.accent)
+                    .bg(self.theme.surface),
+            ),
+            _ => Text::from(Line::from(msg)).patch_style(style),
+        };
+        let help_message = Paragraph::new(text).style(Style::default().bg(self.theme.surface));
+        frame.render_widget(help_message, help_area);
+
+        // Vi normal mode keeps a cursor in the input too, for motions to move around
+        if matches!(self.input_mode, InputMode::Editing)
+            || (self.config.vi_mode && matches!(self.input_mode, InputMode::Normal))
+        {
+            let (line, column) = self.input.cursor_position();
+            frame.set_cursor_position(Position::new(
+                input_area.x + (column - self.input_scroll.1) as u16 + 1,
+                input_area.y + (line - self.input_scroll.0) as u16 + 1,
+            ))
+        }
+
+        if let (InputMode::Editing, Some(picker)) = (&self.input_mode, &self.emoji_picker) {
+            self.draw_emoji_picker(frame, picker, input_area);
+            link_regions.clear();
+        }
+        if let (InputMode::Editing, Some(completion)) = (&self.input_mode, &self.completion) {
+            self.draw_completion(frame, completion, input_area);
+            link_regions.clear();
+        }
+
+        if let Some(user_id) = &self.whois {
+            self.draw_whois(frame, user_id);
+            // Popups cover the links; dropping them makes sure they're rewritten on close
+            link_regions.clear();
+        }
+        if self.show_keys {
+            self.draw_keys(frame);
+            link_regions.clear();
+        }
+        if let Some(index) = self.details {
+            self.draw_details(frame, &self.transcript.messages()[index]);
+            link_regions.clear();
+        }
+        if let Some(menu) = &self.message_menu {
+            self.draw_message_menu(frame, menu);
+            link_regions.clear();
+        }
+        if let Some((urls, state)) = &self.url_picker {
+            self.draw_url_picker(frame, urls, state);
+            link_regions.clear();
+        }
+        if let Some((text, state)) = &self.paste_prompt {
+            self.draw_paste_prompt(frame, text, state);
+            link_regions.clear();
+        }
+
+        link_regions
+    }
+
+    // Shortcode suggestions floating just above the input box, under the `:` being completed
+    fn draw_emoji_picker(&self, frame: &mut Frame, picker: &emoji::Picker, input_area: Rect) {
+        let items = picker
+            .matches
+            .iter()
+            .map(|emoji| {
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{} ", emoji.as_str())),
+                    Span::styled(
+                        format!(":{}:", emoji.shortcode().unwrap_or(emoji.name())),
+                        Style::default().fg(self.theme.text),
+                    ),
+                ]))
+            })
+            .collect::<Vec<_>>();
+
+        let frame_area = frame.area();
+        let width = 32.min(frame_area.width);
+        let height = (picker.matches.len() as u16 + 2).min(input_area.y);
+        let (_, column) = self.input.position_of(picker.start);
+        let column = column.saturating_sub(self.input_scroll.1) as u16;
+        let x = (input_area.x + 1 + column).min(frame_area.right().saturating_sub(width));
+        let area = Rect::new(x, input_area.y.saturating_sub(height), width, height);
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(" 😀 Emoji ")
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .highlight_style(Style::default().bg(self.theme.highlight));
+
+        let mut state = ListState::default().with_selected(Some(picker.selected));
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    // Tab candidates floating above the input box, under the word being completed
+    fn draw_completion(
+        &self,
+        frame: &mut Frame,
+        completion: &complete::Completion,
+        input_area: Rect,
+    ) {
+        let items = completion
+            .candidates
+            .iter()
+            .map(|candidate| {
+                ListItem::new(Span::styled(
+                    candidate.label.as_str(),
+                    Style::default().fg(self.theme.text),
+                ))
+            })
+            .collect::<Vec<_>>();
+
+        let frame_area = frame.area();
+        let longest = completion
+            .candidates
+            .iter()
+            .map(|candidate| Line::from(candidate.label.as_str()).width())
+            .max()
+            .unwrap_or(0) as u16;
+        let width = (longest + 2).clamp(16, 48).min(frame_area.width);
+        let rows = completion.candidates.len().min(emoji::PICKER_SIZE) as u16;
+        let height = (rows + 2).min(input_area.y);
+        let (_, column) = self.input.position_of(completion.start);
+        let column = column.saturating_sub(self.input_scroll.1) as u16;
+        let x = (input_area.x + 1 + column).min(frame_area.right().saturating_sub(width));
+        let area = Rect::new(x, input_area.y.saturating_sub(height), width, height);
+
+        let list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(format!(
+                        " ⇥ {}/{} ",
+                        completion.selected + 1,
+                        completion.candidates.len()
+                    ))
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .highlight_style(Style::default().bg(self.theme.highlight));
+
+        let mut state = ListState::default().with_selected(Some(completion.selected));
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+
+    // Every key of the current mode: the keymap's, then the fixed ones
+    fn draw_keys(&self, frame: &mut Frame) {
+        let keymap = &self.config.keymap;
+        let bound = |mode, filter: &dyn Fn(Action) -> bool| {
+            keymap
+                .bindings(mode)
+                .iter()
+                .filter(|(action, keys)| filter(*action) && !keys.is_empty())
+                .map(|(action, keys)| {
+                    let keys = keys.iter().map(ToString::to_string);
+                    (
+                        keys.collect::<Vec<_>>().join(" "),
+                        action.about().to_string(),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        let fixed = |keys: &[(&str, &str)]| {
+            keys.iter()
+                .map(|(keys, about)| (keys.to_string(), about.to_string()))
+                .collect::<Vec<_>>()
+        };
+
+        let (mode, rows) = match self.input_mode {
+            InputMode::Normal if self.sidebar_focused => {
+                let mut rows = fixed(keymap::SIDEBAR_KEYS);
+                rows.extend(bound(keymap::Mode::Normal, &|action| {
+                    matches!(action, Action::Quit | Action::Help | Action::Sidebar)
+                }));
+                ("sidebar", rows)
+            }
+            InputMode::Normal if self.config.vi_mode => {
+                let mut rows = fixed(keymap::VI_KEYS);
+                rows.extend(bound(keymap::Mode::Normal, &|_| true));
+                ("vi normal, vi keys first", rows)
+            }
+            InputMode::Normal => ("normal", bound(keymap::Mode::Normal, &|_| true)),
+            InputMode::Editing => {
+                let mut rows = bound(keymap::Mode::Editing, &|_| true);
+                rows.extend(fixed(keymap::EDITING_KEYS));
+                ("typing", rows)
+            }
+            InputMode::Selecting => {
+                let mut rows = fixed(keymap::SELECTING_KEYS);
+                rows.extend(
+                    MessageAction::ALL
+                        .iter()
+                        .map(|action| (action.key().to_string(), action.label().to_lowercase())),
+                );
+                ("selecting", rows)
+            }
+        };
+
+        let key_width = rows.iter().map(|(keys, _)| keys.width()).max().unwrap_or(0);
+        let lines = rows
+            .iter()
+            .map(|(keys, about)| {
+                Line::from(vec![
+                    Span::styled(
+                        format!("{:<width$}  ", keys, width = key_width),
+                        Style::default().fg(self.theme.accent),
+                    ),
+                    Span::styled(about.as_str(), Style::default().fg(self.theme.text)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+        let area = centered_rect(frame.area(), width, lines.len() as u16 + 2);
+        let card = Paragraph::new(lines).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(Style::default().fg(self.theme.accent))
+                .style(Style::default().bg(self.theme.surface))
+                .padding(Padding::horizontal(1))
+                .title(format!(" ⌨️ Keys: {} ", mode))
+                .title_bottom(" any key to close ")
+                .title_style(Style::default().fg(self.theme.accent)),
+        );
+        frame.render_widget(Clear, area);
+        frame.render_widget(card, area);
+    }
+
+    fn draw_message_menu(&self, frame: &mut Frame, menu: &MessageMenu) {
+        let key = Style::default().fg(self.theme.faint);
+        let label = Style::default().fg(self.theme.text);
+        let (title, hint, items): (_, _, Vec<ListItem>) = match &menu.items {
+            MenuItems::Actions(actions) => (
+                " 💬 Message ",
+                " Enter or a key to pick, Esc to cancel ",
+                actions
+                    .iter()
+                    .map(|action| {
+                        ListItem::new(Line::from(vec![
+                            Span::styled(format!("{} ", action.key()), key),
+                            Span::styled(action.label(), label),
+                        ]))
+                    })
+                    .collect(),
+            ),
+            MenuItems::Reactions => (
+                " 😀 React ",
+                " Enter/1-8 to send, Esc to cancel ",
+                actions::REACTIONS
+                    .iter()
+                    .enumerate()
+                    .map(|(index, emoji)| {
+                        ListItem::new(Line::from(vec![
+                            Span::styled(format!("{} ", index + 1), key),
+                            Span::raw(*emoji),
+                        ]))
+                    })
+                    .collect(),
+            ),
+            MenuItems::ConfirmDelete => (
+                " 🗑️ Delete for everyone? ",
+                " y/n ",
+                vec![
+                    ListItem::new(Line::from(vec![
+                        Span::styled("y ", key),
+                        Span::styled("Delete it", Style::default().fg(self.theme.error)),
+                    ])),
+                    ListItem::new(Line::from(vec![
+                        Span::styled("n ", key),
+                        Span::styled("Keep it", label),
+                    ])),
+                ],
+            ),
+        };
+
+        let area = centered_rect(frame.area(), 40, items.len() as u16 + 2);
+        let menu_list = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(title)
+                    .title_bottom(hint)
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .highlight_style(Style::default().bg(self.theme.highlight));
+
+        let mut state = menu.state.clone();
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(menu_list, area, &mut state);
+    }
+
+    fn draw_details(&self, frame: &mut Frame, message: &transcript::Message) {
+        let label = Style::default().fg(self.theme.text);
+        let value = Style::default().fg(self.theme.accent);
+        let author = if message.is_chat() {
+            Span::styled(
+                message.author.as_str(),
+                Style::default()
+                    .fg(self.theme.user_color(&message.author))
+                    .add_modifier(Modifier::BOLD),
+            )
+        } else {
+            Span::styled("System", Style::default().fg(self.theme.system_text))
+        };
+        let id = match message.server_id {
+            Some(id) => format!("#{}", id),
+            None => format!("local {}", message.id),
+        };
+
+        let mut lines = vec![
+            Line::from(author),
+            Line::from(""),
+            Line::from(vec![
+                Span::styled("Sent: ", label),
+                Span::styled(time::format_exact(message.timestamp), value),
+            ]),
+            Line::from(vec![
+                Span::styled("That was ", label),
+                Span::styled(time::format_ago(message.timestamp), value),
+            ]),
+            Line::from(vec![Span::styled("Id: ", label), Span::styled(id, value)]),
+        ];
+        if message.deleted {
+            lines.push(Line::from(Span::styled("Deleted", value)));
+        } else if message.edited {
+            lines.push(Line::from(Span::styled("Edited", value)));
+        }
+
+        let area = centered_rect(frame.area(), 44, lines.len() as u16 + 2);
+        let card = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .padding(Padding::horizontal(1))
+                    .title(" 🔍 Details ")
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .alignment(ratatui::layout::Alignment::Center);
+        frame.render_widget(Clear, area);
+        frame.render_widget(card, area);
+    }
+
+    fn draw_url_picker(&self, frame: &mut Frame, urls: &[String], state: &ListState) {
+        let items = urls
+            .iter()
+            .enumerate()
+            .map(|(index, url)| {
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!("{} ", index + 1),
+                        Style::default().fg(self.theme.faint),
+                    ),
+                    Span::styled(
+                        url.as_str(),
+                        Style::default()
+                            .fg(self.theme.link)
+                            .add_modifier(Modifier::UNDERLINED),
+                    ),
+                ]))
+            })
+            .collect::<Vec<_>>();
+
+        let width = frame.area().width.saturating_sub(4).min(72);
+        let area = centered_rect(frame.area(), width, urls.len() as u16 + 2);
+        let picker = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(" 🌐 Open Link ")
+                    .title_bottom(" Enter/1-9 to open, Esc to cancel ")
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .highlight_style(Style::default().bg(self.theme.highlight));
+
+        let mut state = state.clone();
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(picker, area, &mut state);
+    }
+
+    fn draw_paste_prompt(&self, frame: &mut Frame, text: &str, state: &ListState) {
+        let items = PASTE_ACTIONS
+            .iter()
+            .enumerate()
+            .map(|(index, (_, label))| {
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!("{} ", index + 1),
+                        Style::default().fg(self.theme.faint),
+                    ),
+                    Span::styled(*label, Style::default().fg(self.theme.text)),
+                ]))
+            })
+            .collect::<Vec<_>>();
+
+        let width = frame.area().width.saturating_sub(4).min(40);
+        let area = centered_rect(frame.area(), width, PASTE_ACTIONS.len() as u16 + 2);
+        let prompt = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(format!(" 📋 Paste {} lines? ", text.lines().count()))
+                    .title_bottom(" Enter/1-3 to pick, Esc to drop ")
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .highlight_style(Style::default().bg(self.theme.highlight));
+
+        let mut state = state.clone();
+        frame.render_widget(Clear, area);
+        frame.render_stateful_widget(prompt, area, &mut state);
+    }
+
+    fn draw_sidebar(&self, frame: &mut Frame, area: Rect) {
+        let items = self
+            .presence
+            .values()
+            .map(|user| {
+                let activity = user.activity();
+                let mut spans = vec![
+                    Span::styled(
+                        format!("{} ", activity.symbol()),
+                        Style::default().fg(self.theme.activity_color(activity)),
+                    ),
+                    Span::styled(
+                        user.id.as_str(),
+                        Style::default().fg(self.theme.user_color(&user.id)),
+                    ),
+                ];
+                if user.id == self.user_id {
+                    spans.push(Span::styled(
+                        " (you)",
+                        Style::default().fg(self.theme.faint),
+                    ));
+                }
+                ListItem::new(vec![
+                    Line::from(spans),
+                    Line::from(Span::styled(
+                        format!(
+                            "  joined {} · {}",
+                            time::format_clock(user.joined_at),
+                            activity.label()
+                        ),
+                        Style::default().fg(self.theme.faint),
+                    )),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        let border_color = if self.sidebar_focused {
+            self.theme.accent
+        } else {
+            self.theme.border
+        };
+        let sidebar = List::new(items)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(border_color))
+                    .style(Style::default().bg(self.theme.surface))
+                    .title(" 👥 Online ")
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .highlight_style(if self.sidebar_focused {
+                Style::default().bg(self.theme.highlight)
+            } else {
+                Style::default()
+            });
+
+        let mut sidebar_state = self.sidebar_state.clone();
+        frame.render_stateful_widget(sidebar, area, &mut sidebar_state);
+    }
+
+    fn draw_whois(&self, frame: &mut Frame, user_id: &str) {
+        let label = Style::default().fg(self.theme.text);
+        let value = Style::default().fg(self.theme.accent);
+        let sent = self
+            .transcript
+            .messages()
+            .iter()
+            .filter(|message| message.author == user_id)
+            .count();
+
+        let mut lines = vec![Line::from(Span::styled(
+            user_id,
+            Style::default()
+                .fg(self.theme.user_color(user_id))
+                .add_modifier(Modifier::BOLD),
+        ))];
+        if let Some(user) = self.presence.get(user_id) {
+            let activity = user.activity();
+            lines.extend([
+                Line::from(""),
+                Line::from(vec![
+                    Span::styled("Status: ", label),
+                    Span::styled(
+                        format!("{} {}", activity.symbol(), activity.label()),
+                        Style::default().fg(self.theme.activity_color(activity)),
+                    ),
+                ]),
+                Line::from(vec![
+                    Span::styled("Joined: ", label),
+                    Span::styled(time::format_exact(user.joined_at), value),
+                ]),
+                Line::from(vec![
+                    Span::styled("Last active: ", label),
+                    Span::styled(time::format_ago(user.last_active_at), value),
+                ]),
+            ]);
+        }
+        lines.push(Line::from(vec![
+            Span::styled("Messages seen: ", label),
+            Span::styled(sent.to_string(), value),
+        ]));
+
+        let area = centered_rect(frame.area(), 44, lines.len() as u16 + 2);
+        let card = Paragraph::new(lines)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_type(BorderType::Rounded)
+                    .border_style(Style::default().fg(self.theme.accent))
+                    .style(Style::default().bg(self.theme.surface))
+                    .padding(Padding::horizontal(1))
+                    .title(" 🪪 Whois ")
+                    .title_style(Style::default().fg(self.theme.accent)),
+            )
+            .alignment(ratatui::layout::Alignment::Center);
+        frame.render_widget(Clear, area);
+        frame.render_widget(card, area);
+    }
+}
+
+// Writes the draft $EDITOR opens, readable only by us. The temp dir is shared, so it
+// won't open anything already at `path`, like a symlink planted there.
+fn write_draft(path: &std::path::Path, text: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(text.as_bytes())
+}
+
+// Splits `text` into lines with the bytes in `highlight` drawn in `style`
+fn highlight_text(text: &str, highlight: std::ops::Range<usize>, style: Style) -> Text<'_> {
+    let mut offset = 0;
+    text.split('\n')
+        .map(|line| {
+            let start = offset;
+            offset += line.len() + 1;
+            let from = highlight.start.clamp(start, start + line.len()) - start;
+            let to = highlight.end.clamp(start, start + line.len()) - start;
+            Line::from(vec![
+                Span::raw(&line[..from]),
+                Span::styled(&line[from..to], style),
+                Span::raw(&line[to..]),
+            ])
+        })
+        .collect::<Vec<_>>()
+        .into()
+}
+
+// Fixed-size rect in the middle of `area`, shrunk to fit if needed
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let [area] = Layout::horizontal([Constraint::Length(width)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::vertical([Constraint::Length(height)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
 }
@@ -0,0 +1,644 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use pulldown_cmark::{CodeBlockKind, Event, Options, Parser, Tag, TagEnd, TextMergeStream};
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use syntect::{
+    easy::HighlightLines, highlighting::ThemeSet, parsing::SyntaxSet, util::LinesWithEndings,
+};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::links;
+use crate::theme::{ColorDepth, Theme};
+
+// Syntect theme for code when the UI theme names one syntect doesn't have
+const FALLBACK_CODE_THEME: &str = "base16-ocean.dark";
+
+// Inline styles layered over whatever bubble the message sits in, taken from the UI theme
+#[derive(Clone)]
+pub struct Styles {
+    code: Style,
+    link: Style,
+    quote: Style,
+    marker: Style,
+    label: Style,
+    clip: Style,
+    code_theme: String,
+    // Highlighted code comes out as RGB and gets squeezed down like the rest of the theme
+    depth: ColorDepth,
+}
+
+impl Styles {
+    pub fn new(theme: &Theme, depth: ColorDepth) -> Self {
+        Self {
+            code: Style::new().fg(theme.code).bg(theme.code_background),
+            link: Style::new()
+                .fg(theme.link)
+                .add_modifier(Modifier::UNDERLINED),
+            quote: Style::new().fg(theme.quote).add_modifier(Modifier::ITALIC),
+            marker: Style::new().fg(theme.accent),
+            label: Style::new()
+                .fg(theme.background)
+                .bg(theme.accent)
+                .add_modifier(Modifier::BOLD),
+            clip: Style::new().fg(theme.faint).bg(theme.code_background),
+            code_theme: theme.code_theme.clone(),
+            depth,
+        }
+    }
+}
+
+impl Default for Styles {
+    fn default() -> Self {
+        Self::new(&Theme::dark(), ColorDepth::TrueColor)
+    }
+}
+
+// Loading syntect's bundled syntaxes is slow, so do it once
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn code_theme(name: &str) -> &'static syntect::highlighting::Theme {
+    static THEMES: OnceLock<HashMap<String, syntect::highlighting::Theme>> = OnceLock::new();
+    let themes = THEMES.get_or_init(|| ThemeSet::load_defaults().themes.into_iter().collect());
+    themes
+        .get(name)
+        .or_else(|| themes.get(FALLBACK_CODE_THEME))
+        .expect("syntect bundles its default themes")
+}
+
+// A fenced or indented code block, kept verbatim
+pub struct CodeBlock {
+    pub lang: Option<String>,
+    pub code: String,
+}
+
+// A message laid out for a given width
+pub struct Rendered {
+    pub lines: Vec<Line<'static>>,
+    pub links: Vec<LinkSpan>,
+}
+
+// Where a link landed: `width` columns starting at `column` of `lines[line]`
+#[derive(Clone, Debug)]
+pub struct LinkSpan {
+    pub line: usize,
+    pub column: usize,
+    pub width: usize,
+    pub url: String,
+}
+
+// Styled text, remembering which link (if any) it belongs to
+#[derive(Clone)]
+struct Run {
+    span: Span<'static>,
+    link: Option<usize>,
+}
+
+// One paragraph, heading, list item or quote line, before wrapping
+struct Block {
+    first_prefix: Vec<Span<'static>>,
+    prefix: Vec<Span<'static>>,
+    runs: Vec<Run>,
+}
+
+enum Segment {
+    Text(Block),
+    Code(Vec<Span<'static>>, Vec<Span<'static>>, CodeBlock),
+}
+
+struct ListLevel {
+    next_number: Option<u64>,
+    marker_width: usize,
+}
+
+// Renders message text as wrapped, styled lines no wider than `width` columns
+pub fn render(text: &str, width: usize, styles: &Styles) -> Rendered {
+    let (segments, urls) = parse(text, styles);
+    let mut rendered = Rendered {
+        lines: Vec::new(),
+        links: Vec::new(),
+    };
+    for segment in segments {
+        match segment {
+            Segment::Text(block) => {
+                let (lines, links) = wrap_block(block, width, &urls);
+                let offset = rendered.lines.len();
+                rendered
+                    .links
+                    .extend(links.into_iter().map(|link| LinkSpan {
+                        line: link.line + offset,
+                        ..link
+                    }));
+                rendered.lines.extend(lines);
+            }
+            Segment::Code(first_prefix, prefix, block) => {
+                rendered
+                    .lines
+                    .extend(render_code(first_prefix, prefix, &block, width, styles));
+            }
+        }
+    }
+    if rendered.lines.is_empty() {
+        rendered.lines.push(Line::default());
+    }
+    rendered
+}
+
+// Plain text wrapped to `width` display columns, for notices that aren't Markdown
+pub fn wrap_plain(text: &str, width: usize) -> Vec<String> {
+    let block = Block {
+        first_prefix: Vec::new(),
+        prefix: Vec::new(),
+        runs: vec![Run {
+            span: Span::raw(text.to_string()),
+            link: None,
+        }],
+    };
+    wrap_block(block, width, &[])
+        .0
+        .into_iter()
+        .map(|line| line.to_string())
+        .collect()
+}
+
+// Every link target in the message, Markdown links and bare URLs alike, without repeats
+pub fn urls(text: &str) -> Vec<String> {
+    let mut urls = parse(text, &Styles::default()).1;
+    let mut seen = std::collections::HashSet::new();
+    urls.retain(|url| seen.insert(url.clone()));
+    urls
+}
+
+// Every code block in the message, in order
+pub fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    parse(text, &Styles::default())
+        .0
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Code(_, _, block) => Some(block),
+            Segment::Text(_) => None,
+        })
+        .collect()
+}
+
+// Walks pulldown-cmark events, collecting styled runs into blocks
+struct BlockBuilder<'a> {
+    styles: &'a Styles,
+    segments: Vec<Segment>,
+    runs: Vec<Run>,
+    // Link targets, indexed by `Run::link`
+    urls: Vec<String>,
+    // Markdown link we're inside of, if any
+    link: Option<usize>,
+    quote_depth: usize,
+    lists: Vec<ListLevel>,
+    // Marker still waiting for the first block of the current list item
+    pending_marker: Option<String>,
+    // Code block being collected, if we're inside one
+    code: Option<CodeBlock>,
+}
+
+impl<'a> BlockBuilder<'a> {
+    fn new(styles: &'a Styles) -> Self {
+        Self {
+            styles,
+            segments: Vec::new(),
+            runs: Vec::new(),
+            urls: Vec::new(),
+            link: None,
+            quote_depth: 0,
+            lists: Vec::new(),
+            pending_marker: None,
+            code: None,
+        }
+    }
+
+    // Prefixes for the first and following lines of the next block
+    fn prefixes(&mut self) -> (Vec<Span<'static>>, Vec<Span<'static>>) {
+        let mut prefix = Vec::new();
+        if self.quote_depth > 0 {
+            prefix.push(Span::styled(
+                "▎ ".repeat(self.quote_depth),
+                self.styles.quote,
+            ));
+        }
+        let indent: usize = self.lists.iter().map(|level| level.marker_width).sum();
+        let mut first_prefix = prefix.clone();
+        match self.pending_marker.take() {
+            Some(marker) => {
+                let outer = indent - self.lists.last().map_or(0, |level| level.marker_width);
+                first_prefix.push(Span::raw(" ".repeat(outer)));
+                first_prefix.push(Span::styled(marker, self.styles.marker));
+            }
+            None => first_prefix.push(Span::raw(" ".repeat(indent))),
+        }
+        prefix.push(Span::raw(" ".repeat(indent)));
+        (first_prefix, prefix)
+    }
+
+    fn push(&mut self, span: Span<'static>) {
+        self.runs.push(Run {
+            span,
+            link: self.link,
+        });
+    }
+
+    // Plain text, with any bare URLs in it turned into links
+    fn push_text(&mut self, text: &str, style: Style) {
+        if self.link.is_some() {
+            self.push(Span::styled(text.to_string(), style));
+            return;
+        }
+        let mut last = 0;
+        for url in links::find_urls(text) {
+            if url.start > last {
+                self.push(Span::styled(text[last..url.start].to_string(), style));
+            }
+            self.urls.push(text[url.clone()].to_string());
+            self.runs.push(Run {
+                span: Span::styled(text[url.clone()].to_string(), style.patch(self.styles.link)),
+                link: Some(self.urls.len() - 1),
+            });
+            last = url.end;
+        }
+        if last < text.len() {
+            self.push(Span::styled(text[last..].to_string(), style));
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.runs.is_empty() {
+            return;
+        }
+        let (first_prefix, prefix) = self.prefixes();
+        self.segments.push(Segment::Text(Block {
+            first_prefix,
+            prefix,
+            runs: std::mem::take(&mut self.runs),
+        }));
+    }
+
+    fn finish_code(&mut self) {
+        if let Some(mut block) = self.code.take() {
+            // The closing fence leaves a trailing newline behind
+            if block.code.ends_with('\n') {
+                block.code.pop();
+            }
+            let (first_prefix, prefix) = self.prefixes();
+            self.segments
+                .push(Segment::Code(first_prefix, prefix, block));
+        }
+    }
+}
+
+fn parse(text: &str, styles: &Styles) -> (Vec<Segment>, Vec<String>) {
+    // Merged so a URL split across several text events is still seen whole
+    let parser = TextMergeStream::new(Parser::new_ext(text, Options::ENABLE_STRIKETHROUGH));
+    let mut builder = BlockBuilder::new(styles);
+    let mut styles = vec![Style::default()];
+
+    for event in parser {
+        let style = *styles.last().unwrap();
+        match event {
+            Event::Start(tag) => match tag {
+                Tag::Paragraph | Tag::Heading { .. } => {
+                    builder.flush();
+                    let style = match tag {
+                        Tag::Heading { .. } => style.add_modifier(Modifier::BOLD),
+                        _ => style,
+                    };
+                    styles.push(style);
+                }
+                Tag::CodeBlock(kind) => {
+                    builder.flush();
+                    let lang = match kind {
+                        CodeBlockKind::Fenced(info) => info
+                            .split(|c: char| c.is_whitespace() || c == ',')
+                            .next()
+                            .filter(|lang| !lang.is_empty())
+                            .map(str::to_string),
+                        CodeBlockKind::Indented => None,
+                    };
+                    builder.code = Some(CodeBlock {
+                        lang,
+                        code: String::new(),
+                    });
+                    styles.push(style);
+                }
+                Tag::BlockQuote(_) => {
+                    builder.flush();
+                    builder.quote_depth += 1;
+                    styles.push(style.patch(builder.styles.quote));
+                }
+                Tag::List(start) => {
+                    builder.flush();
+                    builder.lists.push(ListLevel {
+                        next_number: start,
+                        marker_width: 2,
+                    });
+                }
+                Tag::Item => {
+                    builder.flush();
+                    if let Some(level) = builder.lists.last_mut() {
+                        let marker = match level.next_number.as_mut() {
+                            Some(number) => {
+                                *number += 1;
+                                format!("{}. ", *number - 1)
+                            }
+                            None => String::from("• "),
+                        };
+                        level.marker_width = marker.width();
+                        builder.pending_marker = Some(marker);
+                    }
+                }
+                Tag::Emphasis => styles.push(style.add_modifier(Modifier::ITALIC)),
+                Tag::Strong => styles.push(style.add_modifier(Modifier::BOLD)),
+                Tag::Strikethrough => styles.push(style.add_modifier(Modifier::CROSSED_OUT)),
+                Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+                    builder.urls.push(dest_url.to_string());
+                    builder.link = Some(builder.urls.len() - 1);
+                    styles.push(style.patch(builder.styles.link));
+                }
+                _ => styles.push(style),
+            },
+            Event::End(tag) => {
+                if styles.len() > 1 && !matches!(tag, TagEnd::List(_) | TagEnd::Item) {
+                    styles.pop();
+                }
+                match tag {
+                    TagEnd::Paragraph | TagEnd::Heading(_) | TagEnd::Item => builder.flush(),
+                    TagEnd::CodeBlock => builder.finish_code(),
+                    TagEnd::BlockQuote(_) => {
+                        builder.flush();
+                        builder.quote_depth = builder.quote_depth.saturating_sub(1);
+                    }
+                    TagEnd::List(_) => {
+                        builder.flush();
+                        builder.lists.pop();
+                    }
+                    TagEnd::Link | TagEnd::Image => builder.link = None,
+                    _ => {}
+                }
+            }
+            Event::Text(text) => match builder.code.as_mut() {
+                Some(block) => block.code.push_str(&text),
+                None => builder.push_text(&text, style),
+            },
+            Event::Code(code) => {
+                builder.push(Span::styled(
+                    code.to_string(),
+                    style.patch(builder.styles.code),
+                ));
+            }
+            Event::SoftBreak | Event::HardBreak => builder.push(Span::raw("\n")),
+            Event::Rule => {
+                builder.flush();
+                builder.push(Span::styled("───", builder.styles.marker));
+                builder.flush();
+            }
+            Event::Html(raw) | Event::InlineHtml(raw) => builder.push_text(&raw, style),
+            _ => {}
+        }
+    }
+    // An unclosed fence still counts as code
+    builder.finish_code();
+    builder.flush();
+    (builder.segments, builder.urls)
+}
+
+// Highlighted, unwrapped code lines under a language label; long lines are clipped
+fn render_code(
+    first_prefix: Vec<Span<'static>>,
+    prefix: Vec<Span<'static>>,
+    block: &CodeBlock,
+    width: usize,
+    styles: &Styles,
+) -> Vec<Line<'static>> {
+    let available = width.saturating_sub(spans_width(&prefix)).max(2);
+    let syntaxes = syntaxes();
+    let syntax = block
+        .lang
+        .as_deref()
+        .and_then(|lang| syntaxes.find_syntax_by_token(lang))
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, code_theme(&styles.code_theme));
+
+    // Tabs have no width of their own, so expand them before measuring anything
+    let code = block.code.replace('\t', "    ");
+    let block_width = code
+        .lines()
+        .map(|line| line.width())
+        .max()
+        .unwrap_or(0)
+        .min(available);
+
+    let mut label = first_prefix;
+    label.push(Span::styled(
+        format!(" {} ", block.lang.as_deref().unwrap_or("code")),
+        styles.label,
+    ));
+    let mut lines = vec![Line::from(label)];
+
+    for code_line in LinesWithEndings::from(&code) {
+        let overflows = code_line.trim_end_matches(['\r', '\n']).width() > available;
+        // Leave a column for the clip marker when the line doesn't fit
+        let limit = if overflows { available - 1 } else { available };
+        let mut spans = prefix.clone();
+        let mut line_width = 0;
+        let regions = highlighter
+            .highlight_line(code_line, syntaxes)
+            .unwrap_or_else(|_| vec![(Default::default(), code_line)]);
+
+        'regions: for (highlight, text) in regions {
+            let style = styles.code.fg(styles.depth.adapt(Color::Rgb(
+                highlight.foreground.r,
+                highlight.foreground.g,
+                highlight.foreground.b,
+            )));
+            let mut piece = String::new();
+            let text = text.trim_end_matches(['\r', '\n']);
+            for grapheme in text.graphemes(true) {
+                let grapheme_width = grapheme.width();
+                if line_width + grapheme_width > limit {
+                    spans.push(Span::styled(piece, style));
+                    break 'regions;
+                }
+                piece.push_str(grapheme);
+                line_width += grapheme_width;
+            }
+            spans.push(Span::styled(piece, style));
+        }
+
+        let fill = block_width.saturating_sub(line_width + overflows as usize);
+        spans.push(Span::styled(" ".repeat(fill), styles.code));
+        if overflows {
+            spans.push(Span::styled("›", styles.clip));
+        }
+        lines.push(Line::from(spans));
+    }
+    lines
+}
+
+enum Token {
+    Word(Vec<Run>),
+    Space(Run),
+    Break,
+}
+
+// Splits styled runs into words, keeping a word together even when its style changes midway
+fn tokenize(runs: Vec<Run>) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word: Vec<Run> = Vec::new();
+
+    for run in runs {
+        let piece_of = |content: String| Run {
+            span: Span::styled(content, run.span.style),
+            link: run.link,
+        };
+        let mut piece = String::new();
+        for c in run.span.content.chars() {
+            if c.is_whitespace() {
+                if !piece.is_empty() {
+                    word.push(piece_of(std::mem::take(&mut piece)));
+                }
+                if !word.is_empty() {
+                    tokens.push(Token::Word(std::mem::take(&mut word)));
+                }
+                tokens.push(if c == '\n' {
+                    Token::Break
+                } else {
+                    Token::Space(piece_of(String::from(" ")))
+                });
+            } else {
+                piece.push(c);
+            }
+        }
+        if !piece.is_empty() {
+            word.push(piece_of(piece));
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(Token::Word(word));
+    }
+    tokens
+}
+
+fn spans_width(spans: &[Span]) -> usize {
+    spans.iter().map(|span| span.content.width()).sum()
+}
+
+fn runs_width(runs: &[Run]) -> usize {
+    runs.iter().map(|run| run.span.content.width()).sum()
+}
+
+// Accumulates wrapped lines, tracking where link runs end up
+struct LineBuilder<'a> {
+    prefix: &'a [Span<'static>],
+    urls: &'a [String],
+    lines: Vec<Line<'static>>,
+    links: Vec<LinkSpan>,
+    line: Vec<Span<'static>>,
+    width: usize,
+    has_content: bool,
+}
+
+impl LineBuilder<'_> {
+    fn push(&mut self, run: Run) {
+        let run_width = run.span.content.width();
+        if let Some(link) = run.link {
+            let line = self.lines.len();
+            match self.links.last_mut() {
+                // Continue the previous span when the same link carries on along this line
+                Some(last)
+                    if last.line == line
+                        && last.column + last.width == self.width
+                        && last.url == self.urls[link] =>
+                {
+                    last.width += run_width;
+                }
+                _ => self.links.push(LinkSpan {
+                    line,
+                    column: self.width,
+                    width: run_width,
+                    url: self.urls[link].clone(),
+                }),
+            }
+        }
+        self.line.push(run.span);
+        self.width += run_width;
+        self.has_content = true;
+    }
+
+    fn break_line(&mut self) {
+        let line = std::mem::replace(&mut self.line, self.prefix.to_vec());
+        self.lines.push(Line::from(line));
+        self.width = spans_width(self.prefix);
+        self.has_content = false;
+    }
+}
+
+fn wrap_block(block: Block, width: usize, urls: &[String]) -> (Vec<Line<'static>>, Vec<LinkSpan>) {
+    let mut builder = LineBuilder {
+        prefix: &block.prefix,
+        urls,
+        lines: Vec::new(),
+        links: Vec::new(),
+        width: spans_width(&block.first_prefix),
+        line: block.first_prefix.clone(),
+        has_content: false,
+    };
+    let mut pending_space: Option<Run> = None;
+
+    for token in tokenize(block.runs) {
+        match token {
+            Token::Break => {
+                builder.break_line();
+                pending_space = None;
+            }
+            Token::Space(space) => {
+                if builder.has_content && pending_space.is_none() {
+                    pending_space = Some(space);
+                }
+            }
+            Token::Word(pieces) => {
+                let word_width = runs_width(&pieces);
+                let space_width = pending_space.is_some() as usize;
+                if builder.has_content && builder.width + space_width + word_width > width {
+                    builder.break_line();
+                } else if let Some(space) = pending_space {
+                    builder.push(space);
+                }
+                pending_space = None;
+
+                if builder.width + word_width <= width {
+                    pieces.into_iter().for_each(|piece| builder.push(piece));
+                    continue;
+                }
+
+                // Too long for any line: break it wherever it runs out of room, keeping
+                // each grapheme (a ZWJ emoji, a letter with its accents) in one piece
+                for piece in pieces {
+                    for grapheme in piece.span.content.graphemes(true) {
+                        if builder.has_content && builder.width + grapheme.width() > width {
+                            builder.break_line();
+                        }
+                        builder.push(Run {
+                            span: Span::styled(grapheme.to_string(), piece.span.style),
+                            link: piece.link,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    let line = std::mem::take(&mut builder.line);
+    builder.lines.push(Line::from(line));
+    (builder.lines, builder.links)
+}
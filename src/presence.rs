@@ -0,0 +1,52 @@
+use serde::Deserialize;
+
+use crate::time::now_millis;
+
+// No messages or typing for this long marks a user as idle, then away
+const IDLE_AFTER_MS: u64 = 2 * 60 * 1000;
+const AWAY_AFTER_MS: u64 = 10 * 60 * 1000;
+
+// A connected user as reported by the server
+#[derive(Deserialize, Clone, Debug)]
+pub struct PresenceUser {
+    pub id: String,
+    #[serde(rename = "joinedAt")]
+    pub joined_at: u64,
+    #[serde(rename = "lastActiveAt")]
+    pub last_active_at: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Activity {
+    Active,
+    Idle,
+    Away,
+}
+
+impl PresenceUser {
+    pub fn activity(&self) -> Activity {
+        match now_millis().saturating_sub(self.last_active_at) {
+            idle if idle >= AWAY_AFTER_MS => Activity::Away,
+            idle if idle >= IDLE_AFTER_MS => Activity::Idle,
+            _ => Activity::Active,
+        }
+    }
+}
+
+impl Activity {
+    pub fn label(self) -> &'static str {
+        match self {
+            Activity::Active => "active",
+            Activity::Idle => "idle",
+            Activity::Away => "away",
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Activity::Active => "●",
+            Activity::Idle => "◐",
+            Activity::Away => "○",
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::io::{self, stdout};
+
+use ratatui::{
+    DefaultTerminal,
+    crossterm::{
+        event::{
+            DisableBracketedPaste, DisableMouseCapture, EnableBracketedPaste, EnableMouseCapture,
+            KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+        },
+        execute,
+        terminal::{EnterAlternateScreen, enable_raw_mode, supports_keyboard_enhancement},
+    },
+};
+
+// Terminal modes we switch on beyond ratatui's raw mode and alternate screen,
+// remembered so they can be switched off again on exit or while suspended
+pub struct Modes {
+    keyboard_enhanced: bool,
+    // Mouse capture, which the user can turn off to get the terminal's own text selection back
+    mouse: bool,
+}
+
+impl Modes {
+    // Must run after raw mode is on, since detection reads the terminal's reply
+    pub fn detect(mouse: bool) -> Self {
+        Self {
+            // Terminals speaking the kitty keyboard protocol can tell Shift+Enter apart from Enter
+            keyboard_enhanced: supports_keyboard_enhancement().unwrap_or(false),
+            mouse,
+        }
+    }
+
+    pub fn mouse(&self) -> bool {
+        self.mouse
+    }
+
+    pub fn set_mouse(&mut self, mouse: bool) -> io::Result<()> {
+        self.mouse = mouse;
+        if mouse {
+            execute!(stdout(), EnableMouseCapture)
+        } else {
+            execute!(stdout(), DisableMouseCapture)
+        }
+    }
+
+    pub fn enable(&self) -> io::Result<()> {
+        // Pastes arrive as one Event::Paste instead of a key per character
+        execute!(stdout(), EnableBracketedPaste)?;
+        if self.keyboard_enhanced {
+            execute!(
+                stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+            )?;
+        }
+        if self.mouse {
+            execute!(stdout(), EnableMouseCapture)?;
+        }
+        Ok(())
+    }
+
+    pub fn disable(&self) -> io::Result<()> {
+        execute!(stdout(), DisableBracketedPaste)?;
+        if self.keyboard_enhanced {
+            execute!(stdout(), PopKeyboardEnhancementFlags)?;
+        }
+        if self.mouse {
+            execute!(stdout(), DisableMouseCapture)?;
+        }
+        Ok(())
+    }
+
+    // Hands the terminal back to the shell so another program can use it
+    pub fn suspend(&self) -> io::Result<()> {
+        self.disable()?;
+        ratatui::restore();
+        Ok(())
+    }
+
+    // Takes the terminal back after `suspend` and forces a full redraw
+    pub fn resume(&self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen)?;
+        self.enable()?;
+        terminal.clear()
+    }
+}
@@ -0,0 +1,421 @@
+use std::fs;
+
+use ratatui::style::Color;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+use crate::presence::Activity;
+
+// Names `/theme` and the `theme` config key accept without a theme file
+pub const BUILT_IN: [&str; 4] = ["dark", "light", "solarized", "high-contrast"];
+
+// Every color the UI draws with. Theme files use the same kebab-case keys.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Theme {
+    // Behind everything
+    pub background: Color,
+    // Panels, popups and the help line
+    pub surface: Color,
+    pub border: Color,
+    // Titles, focused borders and anything that should pop
+    pub accent: Color,
+    pub text: Color,
+    pub muted: Color,
+    pub faint: Color,
+    // Selected rows in lists and pickers
+    pub highlight: Color,
+    // Band behind the selected message
+    pub selection: Color,
+    pub own_bubble: Color,
+    pub other_bubble: Color,
+    pub bubble_text: Color,
+    pub system_background: Color,
+    pub system_text: Color,
+    pub scrollbar: Color,
+    pub link: Color,
+    pub warning: Color,
+    pub error: Color,
+    pub code: Color,
+    pub code_background: Color,
+    pub quote: Color,
+    pub idle: Color,
+    // Syntect theme used for fenced code
+    pub code_theme: String,
+    // Colors handed out to usernames
+    pub users: Vec<Color>,
+}
+
+impl Theme {
+    // The original dark green look
+    pub fn dark() -> Self {
+        Self {
+            background: Color::Rgb(13, 20, 24),
+            surface: Color::Rgb(17, 27, 33),
+            border: Color::Rgb(69, 90, 100),
+            accent: Color::Rgb(0, 230, 118),
+            text: Color::White,
+            muted: Color::Gray,
+            faint: Color::DarkGray,
+            highlight: Color::Rgb(38, 45, 49),
+            selection: Color::Rgb(20, 60, 52),
+            own_bubble: Color::Rgb(0, 92, 75),
+            other_bubble: Color::Rgb(38, 45, 49),
+            bubble_text: Color::White,
+            system_background: Color::Rgb(25, 38, 45),
+            system_text: Color::Rgb(102, 187, 106),
+            scrollbar: Color::Rgb(0, 150, 136),
+            link: Color::Rgb(41, 182, 246),
+            warning: Color::Rgb(255, 214, 0),
+            error: Color::Rgb(255, 82, 82),
+            code: Color::Rgb(255, 202, 40),
+            code_background: Color::Rgb(28, 35, 39),
+            quote: Color::Rgb(176, 190, 197),
+            idle: Color::Rgb(255, 202, 40),
+            code_theme: "base16-ocean.dark".to_string(),
+            users: vec![
+                Color::Rgb(0, 230, 118),
+                Color::Rgb(41, 182, 246),
+                Color::Rgb(255, 202, 40),
+                Color::Rgb(239, 83, 80),
+                Color::Rgb(171, 71, 188),
+                Color::Rgb(255, 112, 67),
+                Color::Rgb(38, 198, 218),
+                Color::Rgb(212, 225, 87),
+                Color::Rgb(236, 64, 122),
+                Color::Rgb(126, 87, 194),
+            ],
+        }
+    }
+
+    pub fn light() -> Self {
+        Self {
+            background: Color::Rgb(250, 250, 250),
+            surface: Color::Rgb(238, 241, 243),
+            border: Color::Rgb(176, 190, 197),
+            accent: Color::Rgb(0, 128, 64),
+            text: Color::Rgb(33, 33, 33),
+            muted: Color::Rgb(84, 110, 122),
+            faint: Color::Rgb(144, 164, 174),
+            highlight: Color::Rgb(216, 224, 229),
+            selection: Color::Rgb(200, 230, 201),
+            own_bubble: Color::Rgb(217, 244, 200),
+            other_bubble: Color::Rgb(255, 255, 255),
+            bubble_text: Color::Rgb(17, 27, 33),
+            system_background: Color::Rgb(225, 240, 226),
+            system_text: Color::Rgb(46, 125, 50),
+            scrollbar: Color::Rgb(0, 137, 123),
+            link: Color::Rgb(21, 101, 192),
+            warning: Color::Rgb(230, 108, 0),
+            error: Color::Rgb(198, 40, 40),
+            code: Color::Rgb(173, 20, 87),
+            code_background: Color::Rgb(236, 239, 241),
+            quote: Color::Rgb(96, 125, 139),
+            idle: Color::Rgb(230, 108, 0),
+            code_theme: "InspiredGitHub".to_string(),
+            users: vec![
+                Color::Rgb(0, 128, 64),
+                Color::Rgb(2, 119, 189),
+                Color::Rgb(191, 112, 0),
+                Color::Rgb(198, 40, 40),
+                Color::Rgb(123, 31, 162),
+                Color::Rgb(216, 67, 21),
+                Color::Rgb(0, 131, 143),
+                Color::Rgb(85, 139, 47),
+                Color::Rgb(173, 20, 87),
+                Color::Rgb(69, 39, 160),
+            ],
+        }
+    }
+
+    // Ethan Schoonover's Solarized, dark variant
+    pub fn solarized() -> Self {
+        Self {
+            background: Color::Rgb(0, 43, 54),
+            surface: Color::Rgb(7, 54, 66),
+            border: Color::Rgb(88, 110, 117),
+            accent: Color::Rgb(42, 161, 152),
+            text: Color::Rgb(147, 161, 161),
+            muted: Color::Rgb(131, 148, 150),
+            faint: Color::Rgb(88, 110, 117),
+            highlight: Color::Rgb(0, 43, 54),
+            selection: Color::Rgb(38, 70, 80),
+            own_bubble: Color::Rgb(16, 76, 88),
+            other_bubble: Color::Rgb(0, 43, 54),
+            bubble_text: Color::Rgb(238, 232, 213),
+            system_background: Color::Rgb(0, 43, 54),
+            system_text: Color::Rgb(133, 153, 0),
+            scrollbar: Color::Rgb(42, 161, 152),
+            link: Color::Rgb(38, 139, 210),
+            warning: Color::Rgb(181, 137, 0),
+            error: Color::Rgb(220, 50, 47),
+            code: Color::Rgb(203, 75, 22),
+            code_background: Color::Rgb(0, 43, 54),
+            quote: Color::Rgb(101, 123, 131),
+            idle: Color::Rgb(181, 137, 0),
+            code_theme: "Solarized (dark)".to_string(),
+            users: vec![
+                Color::Rgb(181, 137, 0),
+                Color::Rgb(203, 75, 22),
+                Color::Rgb(220, 50, 47),
+                Color::Rgb(211, 54, 130),
+                Color::Rgb(108, 113, 196),
+                Color::Rgb(38, 139, 210),
+                Color::Rgb(42, 161, 152),
+                Color::Rgb(133, 153, 0),
+            ],
+        }
+    }
+
+    // Plain ANSI colors at full strength, so the terminal's own palette decides
+    pub fn high_contrast() -> Self {
+        Self {
+            background: Color::Black,
+            surface: Color::Black,
+            border: Color::White,
+            accent: Color::LightYellow,
+            text: Color::White,
+            muted: Color::White,
+            faint: Color::Gray,
+            highlight: Color::Blue,
+            selection: Color::Magenta,
+            own_bubble: Color::Blue,
+            other_bubble: Color::DarkGray,
+            bubble_text: Color::White,
+            system_background: Color::Black,
+            system_text: Color::LightGreen,
+            scrollbar: Color::White,
+            link: Color::LightCyan,
+            warning: Color::LightYellow,
+            error: Color::LightRed,
+            code: Color::LightYellow,
+            code_background: Color::Black,
+            quote: Color::Gray,
+            idle: Color::Yellow,
+            code_theme: "base16-eighties.dark".to_string(),
+            users: vec![
+                Color::LightGreen,
+                Color::LightCyan,
+                Color::LightYellow,
+                Color::LightRed,
+                Color::LightMagenta,
+                Color::LightBlue,
+            ],
+        }
+    }
+
+    pub fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::dark()),
+            "light" => Some(Self::light()),
+            "solarized" => Some(Self::solarized()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _ => None,
+        }
+    }
+
+    // A built-in theme, or `themes/<name>.toml` in the config directory. Theme files
+    // start from `extends` (dark unless they say otherwise) and override any keys.
+    pub fn load(name: &str) -> Result<Self, String> {
+        if let Some(theme) = Self::built_in(name) {
+            return Ok(theme);
+        }
+        let path = config::config_dir()
+            .map(|dir| dir.join("themes").join(format!("{}.toml", name)))
+            .ok_or_else(|| format!("No theme called {}", name))?;
+        let text = fs::read_to_string(&path).map_err(|_| {
+            format!(
+                "No theme called {} – built in are {}, or create {}",
+                name,
+                BUILT_IN.join(", "),
+                path.display()
+            )
+        })?;
+        let mut overrides: toml::Table = text
+            .parse()
+            .map_err(|e| format!("Bad theme {}: {}", path.display(), e))?;
+
+        let base = match overrides.remove("extends") {
+            Some(toml::Value::String(base)) => Self::built_in(&base)
+                .ok_or_else(|| format!("{} extends unknown theme {}", path.display(), base))?,
+            Some(_) => return Err(format!("{}: extends must be a name", path.display())),
+            None => Self::dark(),
+        };
+        let mut table = toml::Table::try_from(base).map_err(|e| e.to_string())?;
+        table.extend(overrides);
+        table
+            .try_into()
+            .map_err(|e| format!("Bad theme {}: {}", path.display(), e))
+    }
+
+    // Stable per-name color so everyone sees the same user in the same color
+    pub fn user_color(&self, user_id: &str) -> Color {
+        let hash = user_id.bytes().fold(0u32, |hash, byte| {
+            hash.wrapping_mul(31).wrapping_add(byte as u32)
+        });
+        match self.users.len() {
+            0 => self.text,
+            len => self.users[hash as usize % len],
+        }
+    }
+
+    pub fn activity_color(&self, activity: Activity) -> Color {
+        match activity {
+            Activity::Active => self.accent,
+            Activity::Idle => self.idle,
+            Activity::Away => self.faint,
+        }
+    }
+
+    // The same theme with every color mapped onto what the terminal can show
+    pub fn adapted(mut self, depth: ColorDepth) -> Self {
+        for color in [
+            &mut self.background,
+            &mut self.surface,
+            &mut self.border,
+            &mut self.accent,
+            &mut self.text,
+            &mut self.muted,
+            &mut self.faint,
+            &mut self.highlight,
+            &mut self.selection,
+            &mut self.own_bubble,
+            &mut self.other_bubble,
+            &mut self.bubble_text,
+            &mut self.system_background,
+            &mut self.system_text,
+            &mut self.scrollbar,
+            &mut self.link,
+            &mut self.warning,
+            &mut self.error,
+            &mut self.code,
+            &mut self.code_background,
+            &mut self.quote,
+            &mut self.idle,
+        ]
+        .into_iter()
+        .chain(self.users.iter_mut())
+        {
+            *color = depth.adapt(*color);
+        }
+        self
+    }
+}
+
+// How many colors the terminal can show
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorDepth {
+    TrueColor,
+    Ansi256,
+    Ansi16,
+}
+
+// The 16 ANSI colors with the RGB values most terminals give them
+const ANSI16: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+// Channel values of the 6x6x6 cube in the 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl ColorDepth {
+    // Guesses from $COLORTERM and $TERM, the same way most terminal programs do
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if matches!(colorterm.as_str(), "truecolor" | "24bit") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Ansi256
+        } else {
+            ColorDepth::Ansi16
+        }
+    }
+
+    // Nearest color the terminal can actually show
+    pub fn adapt(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, _) => color,
+            (ColorDepth::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(nearest_256(r, g, b)),
+            (ColorDepth::Ansi16, Color::Rgb(r, g, b)) => nearest_16(r, g, b),
+            (ColorDepth::Ansi16, Color::Indexed(index)) if index >= 16 => {
+                let (r, g, b) = indexed_rgb(index);
+                nearest_16(r, g, b)
+            }
+            _ => color,
+        }
+    }
+}
+
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
+
+fn nearest_16(r: u8, g: u8, b: u8) -> Color {
+    ANSI16
+        .iter()
+        .min_by_key(|(_, rgb)| distance(*rgb, (r, g, b)))
+        .map_or(Color::Reset, |(color, _)| *color)
+}
+
+// Best of the closest cube color and the closest gray ramp color
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    let level = |v: u8| match v {
+        0..48 => 0,
+        48..115 => 1,
+        _ => (v - 35) / 40,
+    };
+    let (cr, cg, cb) = (level(r), level(g), level(b));
+    let cube = 16 + 36 * cr + 6 * cg + cb;
+    let cube_rgb = (
+        CUBE_LEVELS[cr as usize],
+        CUBE_LEVELS[cg as usize],
+        CUBE_LEVELS[cb as usize],
+    );
+
+    let average = ((r as u32 + g as u32 + b as u32) / 3) as u8;
+    let gray_step = (average.saturating_sub(3) / 10).min(23);
+    let gray = 232 + gray_step;
+    let gray_value = 8 + 10 * gray_step;
+
+    if distance((gray_value, gray_value, gray_value), (r, g, b)) < distance(cube_rgb, (r, g, b)) {
+        gray
+    } else {
+        cube
+    }
+}
+
+// RGB value of a 256-color palette entry from the cube or gray ramp
+fn indexed_rgb(index: u8) -> (u8, u8, u8) {
+    match index {
+        0..16 => ANSI16[index as usize].1,
+        16..232 => {
+            let i = index - 16;
+            (
+                CUBE_LEVELS[(i / 36) as usize],
+                CUBE_LEVELS[(i / 6 % 6) as usize],
+                CUBE_LEVELS[(i % 6) as usize],
+            )
+        }
+        _ => {
+            let value = 8 + 10 * (index - 232);
+            (value, value, value)
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use chrono::{DateTime, Local, TimeZone};
+
+// Timestamps on the wire are Unix milliseconds
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+fn local(ms: u64) -> Option<DateTime<Local>> {
+    Local.timestamp_millis_opt(ms as i64).single()
+}
+
+// "14:32"
+pub fn format_clock(ms: u64) -> String {
+    local(ms)
+        .map(|time| time.format("%H:%M").to_string())
+        .unwrap_or_else(|| String::from("--:--"))
+}
+
+// "2025-03-14 14:32:07"
+pub fn format_exact(ms: u64) -> String {
+    local(ms)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+// "just now", "5m ago", "2h ago", "3d ago"
+pub fn format_ago(ms: u64) -> String {
+    let secs = now_millis().saturating_sub(ms) / 1000;
+    match secs {
+        0..60 => String::from("just now"),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
@@ -0,0 +1,928 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use ratatui::{
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::ScrollbarState,
+};
+
+use serde::Deserialize;
+use unicode_width::UnicodeWidthStr;
+
+use crate::markdown::{self, LinkSpan};
+use crate::theme::Theme;
+use crate::time;
+
+// Beside the first line of your own bubbles and everyone else's
+const OWN_GUTTER: &str = " 🫵 ";
+const OTHER_GUTTER: &str = " 🤘 ";
+// After the text of a message that was changed since it was sent
+const EDITED_MARK: &str = " (edited)";
+// Scrollbar positions per message, so it can move between rows of the same one
+const SCROLLBAR_STEPS: usize = 64;
+
+// How messages are drawn
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum LayoutStyle {
+    // Bubbles with a spacer row above and below
+    #[default]
+    Bubbles,
+    // "[14:32] <author> text", one line per message unless it wraps
+    Compact,
+}
+
+// A chat message or server notice in the scrollback
+pub struct Message {
+    // Local sequence number, unique for the session and also given to notices
+    pub id: u64,
+    // The server's id, which edits and deletes refer to; notices that aren't stored have none
+    pub server_id: Option<u64>,
+    pub text: String,
+    pub author: String,
+    pub from_user: bool,
+    pub timestamp: u64,
+    pub edited: bool,
+    pub deleted: bool,
+}
+
+impl Message {
+    // Anything a person wrote, as opposed to "left the chat" notices and the history marker
+    pub fn is_chat(&self) -> bool {
+        self.author != "system" && self.author != "history_loaded"
+    }
+
+    // "[14:32] author: text", for copying without bubbles and gutters
+    pub fn plain(&self) -> String {
+        let clock = time::format_clock(self.timestamp);
+        if self.is_chat() {
+            format!("[{}] {}: {}", clock, self.author, self.text)
+        } else {
+            format!("[{}] * {}", clock, self.text)
+        }
+    }
+}
+
+// A message laid out as screen rows for one width, spacing included
+pub struct Layout {
+    pub lines: Vec<Line<'static>>,
+    // Links with `line` and `column` relative to these rows
+    pub links: Vec<LinkSpan>,
+}
+
+// A row of the transcript: row `line` of message `message`
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+struct Position {
+    message: usize,
+    line: usize,
+}
+
+// One row on screen, as handed back by `Transcript::visible`
+pub struct Row {
+    pub line: Line<'static>,
+    pub links: Vec<LinkSpan>,
+}
+
+// The scrollback, laid out lazily: only messages that come into view (or are scrolled
+// past) are rendered, and each layout is kept until the width or theme changes
+pub struct Transcript {
+    messages: Vec<Message>,
+    next_id: u64,
+    theme: Theme,
+    styles: markdown::Styles,
+    style: LayoutStyle,
+    // Columns of text a bubble can hold at most, however wide the view
+    max_bubble_width: usize,
+    // Layouts by message id, all for `width`
+    layouts: RefCell<HashMap<u64, Rc<Layout>>>,
+    width: Cell<usize>,
+    // Rows the last frame had room for
+    height: Cell<usize>,
+    // The row at the bottom of the view, or None to stay pinned to the latest message
+    anchor: Option<Position>,
+    // Chat messages that arrived while scrolled up, cleared on reaching the bottom
+    unread: usize,
+    // First message that arrived while scrolled up, marked with a divider until you reply
+    unread_since: Option<usize>,
+    // Each row the last frame showed, for mapping clicks and keeping the selection in view
+    shown: RefCell<Vec<Position>>,
+    selected: Option<usize>,
+}
+
+impl Transcript {
+    pub fn new(
+        theme: Theme,
+        styles: markdown::Styles,
+        style: LayoutStyle,
+        max_bubble_width: usize,
+    ) -> Self {
+        Self {
+            messages: Vec::new(),
+            next_id: 0,
+            theme,
+            styles,
+            style,
+            max_bubble_width,
+            layouts: RefCell::new(HashMap::new()),
+            width: Cell::new(0),
+            height: Cell::new(0),
+            anchor: None,
+            unread: 0,
+            unread_since: None,
+            shown: RefCell::new(Vec::new()),
+            selected: None,
+        }
+    }
+
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    pub fn push(
+        &mut self,
+        text: String,
+        author: String,
+        from_user: bool,
+        timestamp: u64,
+        server_id: Option<u64>,
+    ) {
+        self.messages.push(Message {
+            id: self.next_id,
+            server_id,
+            text,
+            author,
+            from_user,
+            timestamp,
+            edited: false,
+            deleted: false,
+        });
+        self.next_id += 1;
+
+        // Only follow new messages from the bottom, so reading back isn't interrupted;
+        // sending one means you're caught up
+        let message = &self.messages[self.messages.len() - 1];
+        if from_user {
+            self.set_unread_since(None);
+            self.set_anchor(None);
+        } else if self.anchor.is_some() && message.is_chat() {
+            if self.unread == 0 {
+                self.set_unread_since(Some(self.messages.len() - 1));
+            }
+            self.unread += 1;
+        }
+    }
+
+    // The divider is part of the layout, so both messages it moves between are laid out again
+    fn set_unread_since(&mut self, index: Option<usize>) {
+        let layouts = self.layouts.get_mut();
+        for index in [self.unread_since, index].into_iter().flatten() {
+            layouts.remove(&self.messages[index].id);
+        }
+        self.unread_since = index;
+    }
+
+    // Chat messages below the view that haven't been scrolled to yet
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    fn set_anchor(&mut self, anchor: Option<Position>) {
+        self.anchor = anchor;
+        if anchor.is_none() {
+            self.unread = 0;
+        }
+    }
+
+    // Replaces the text of the message the server knows as `server_id`
+    pub fn edit(&mut self, server_id: u64, text: String) {
+        if let Some(message) = self.find(server_id) {
+            message.text = text;
+            message.edited = true;
+            let id = message.id;
+            self.layouts.get_mut().remove(&id);
+        }
+    }
+
+    // Deleted messages keep their place, so indexes into the transcript stay valid
+    pub fn delete(&mut self, server_id: u64) {
+        if let Some(message) = self.find(server_id) {
+            message.text.clear();
+            message.deleted = true;
+            let id = message.id;
+            self.layouts.get_mut().remove(&id);
+        }
+    }
+
+    fn find(&mut self, server_id: u64) -> Option<&mut Message> {
+        self.messages
+            .iter_mut()
+            .rev()
+            .find(|message| message.server_id == Some(server_id))
+    }
+
+    // New colors invalidate every layout
+    pub fn restyle(&mut self, theme: Theme, styles: markdown::Styles) {
+        self.theme = theme;
+        self.styles = styles;
+        self.layouts.get_mut().clear();
+    }
+
+    pub fn style(&self) -> LayoutStyle {
+        self.style
+    }
+
+    // Switching layouts keeps the same message at the bottom of the view
+    pub fn set_style(&mut self, style: LayoutStyle) {
+        self.style = style;
+        self.layouts.get_mut().clear();
+        if let Some(anchor) = self.anchor {
+            self.scroll_to_message(anchor.message);
+        }
+    }
+
+    // Index of the selected message, or else the one at the bottom of the view
+    pub fn focused(&self) -> Option<usize> {
+        match (self.selected, self.anchor) {
+            (Some(selected), _) => Some(selected),
+            (None, Some(anchor)) => Some(anchor.message),
+            (None, None) => self.messages.len().checked_sub(1),
+        }
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    pub fn select(&mut self, index: Option<usize>) {
+        self.selected = index.filter(|index| *index < self.messages.len());
+    }
+
+    // Moves the selection one message up or down, starting from the focused message,
+    // and scrolls to keep it in view
+    pub fn select_previous(&mut self) {
+        if let Some(focused) = self.focused() {
+            let index = match self.selected {
+                Some(selected) => selected.saturating_sub(1),
+                None => focused,
+            };
+            self.select(Some(index));
+            self.reveal(index);
+        }
+    }
+
+    pub fn select_next(&mut self) {
+        if let Some(focused) = self.focused() {
+            let index = match self.selected {
+                Some(selected) => (selected + 1).min(self.messages.len() - 1),
+                None => focused,
+            };
+            self.select(Some(index));
+            self.reveal(index);
+        }
+    }
+
+    // Scrolls just enough to show all of message `index`, or its top when it's too tall
+    pub fn reveal(&mut self, index: usize) {
+        let Some(end) = self.end() else {
+            return;
+        };
+        let (first, last) = {
+            let shown = self.shown.borrow();
+            match (shown.first(), shown.last()) {
+                (Some(first), Some(last)) => (*first, *last),
+                _ => return,
+            }
+        };
+        let top = Position {
+            message: index,
+            line: 0,
+        };
+        let bottom = Position {
+            message: index,
+            line: self.rows(index) - 1,
+        };
+        if top < first || bottom > last && self.rows(index) > self.page_height() {
+            // Put its first row at the top of the view
+            let anchor = self.walk_down(top, self.page_height() - 1);
+            self.set_anchor((anchor < end).then_some(anchor));
+        } else if bottom > last {
+            self.scroll_to_message(index);
+        }
+    }
+
+    // Every message the last frame showed some of, as plain text
+    pub fn shown_text(&self) -> String {
+        let shown = self.shown.borrow();
+        let (Some(first), Some(last)) = (shown.first(), shown.last()) else {
+            return String::new();
+        };
+        self.messages[first.message..=last.message]
+            .iter()
+            .filter(|message| !message.deleted)
+            .map(Message::plain)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The message shown on `row` of the view last frame
+    pub fn message_at(&self, row: usize) -> Option<usize> {
+        self.shown
+            .borrow()
+            .get(row)
+            .map(|position| position.message)
+    }
+
+    // Rows the view showed last frame, at least one
+    pub fn page_height(&self) -> usize {
+        self.height.get().max(1)
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let Some(end) = self.end() else {
+            return;
+        };
+        let anchor = self.walk_up(self.anchor.unwrap_or(end), lines);
+        self.set_anchor(self.highest(end).map(|highest| anchor.max(highest)));
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        let (Some(anchor), Some(end)) = (self.anchor, self.end()) else {
+            return;
+        };
+        let anchor = self.walk_down(anchor, lines);
+        self.set_anchor((anchor < end).then_some(anchor));
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        self.set_anchor(self.end().and_then(|end| self.highest(end)));
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.set_anchor(None);
+    }
+
+    // Brings the end of message `index` to the bottom of the view, as far as scrolling allows
+    pub fn scroll_to_message(&mut self, index: usize) {
+        let Some(end) = self.end() else {
+            return;
+        };
+        let anchor = Position {
+            message: index.min(end.message),
+            line: self.rows(index.min(end.message)) - 1,
+        };
+        self.set_anchor(
+            self.highest(end)
+                .filter(|_| anchor < end)
+                .map(|highest| anchor.max(highest)),
+        );
+    }
+
+    // The scale is messages rather than rows, since counting rows would mean laying out the
+    // whole history. Each message gets SCROLLBAR_STEPS of it, shared out between its rows.
+    pub fn scrollbar(&self) -> ScrollbarState {
+        let end = self.messages.len() * SCROLLBAR_STEPS;
+        let position = match (self.selected, self.anchor) {
+            (Some(selected), _) => (selected + 1) * SCROLLBAR_STEPS,
+            (None, Some(anchor)) => {
+                let anchor = self.clamp(anchor);
+                anchor.message * SCROLLBAR_STEPS
+                    + (anchor.line + 1) * SCROLLBAR_STEPS / self.rows(anchor.message)
+            }
+            (None, None) => end,
+        };
+        ScrollbarState::new(end).position(position.saturating_sub(1))
+    }
+
+    // The rows that fit in `height` at `width`, laying out only the messages they come from
+    pub fn visible(&self, width: usize, height: usize) -> Vec<Row> {
+        if self.width.replace(width) != width {
+            self.layouts.borrow_mut().clear();
+        }
+        self.height.set(height);
+        let Some(end) = self.end() else {
+            self.shown.borrow_mut().clear();
+            return Vec::new();
+        };
+        let anchor = self.clamp(self.anchor.unwrap_or(end));
+
+        // Walk up from the bottom row, then fill downwards if the top came first
+        let mut positions = Vec::with_capacity(height);
+        let mut position = Some(anchor);
+        while let Some(current) = position.filter(|_| positions.len() < height) {
+            positions.push(current);
+            position = self.previous(current);
+        }
+        positions.reverse();
+        let mut position = self.next(anchor);
+        while let Some(current) = position.filter(|_| positions.len() < height) {
+            positions.push(current);
+            position = self.next(current);
+        }
+
+        *self.shown.borrow_mut() = positions.clone();
+        positions
+            .into_iter()
+            .map(|position| {
+                let layout = self.layout(position.message);
+                let mut line = layout.lines[position.line].clone();
+                if self.selected == Some(position.message) {
+                    // Fill the row so the band spans the whole width
+                    line.spans
+                        .push(Span::raw(" ".repeat(width.saturating_sub(line.width()))));
+                    line = line.patch_style(Style::default().bg(self.theme.selection));
+                }
+                Row {
+                    line,
+                    links: layout
+                        .links
+                        .iter()
+                        .filter(|link| link.line == position.line)
+                        .cloned()
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    // "──── new since 14:32 ────" across the whole width
+    fn divider(&self, width: usize, timestamp: u64) -> Line<'static> {
+        let label = format!(" new since {} ", time::format_clock(timestamp));
+        let left = width.saturating_sub(label.width()) / 2;
+        let right = width.saturating_sub(label.width() + left);
+        Line::from(format!(
+            "{}{}{}",
+            "─".repeat(left),
+            label,
+            "─".repeat(right)
+        ))
+        .style(Style::default().fg(self.theme.accent))
+    }
+
+    fn layout(&self, index: usize) -> Rc<Layout> {
+        let message = &self.messages[index];
+        self.layouts
+            .borrow_mut()
+            .entry(message.id)
+            .or_insert_with(|| {
+                let width = self.width.get();
+                let mut layout = match self.style {
+                    LayoutStyle::Bubbles => bubble_layout(
+                        message,
+                        width,
+                        self.max_bubble_width,
+                        &self.theme,
+                        &self.styles,
+                    ),
+                    LayoutStyle::Compact => {
+                        compact_layout(message, width, &self.theme, &self.styles)
+                    }
+                };
+                if self.unread_since == Some(index) {
+                    let divider = self.divider(width, message.timestamp);
+                    match self.style {
+                        // It takes the place of the spacer row above the bubble
+                        LayoutStyle::Bubbles => layout.lines[0] = divider,
+                        LayoutStyle::Compact => {
+                            layout.lines.insert(0, divider);
+                            for link in &mut layout.links {
+                                link.line += 1;
+                            }
+                        }
+                    }
+                }
+                Rc::new(layout)
+            })
+            .clone()
+    }
+
+    fn rows(&self, index: usize) -> usize {
+        self.layout(index).lines.len()
+    }
+
+    // Last row of the last message
+    fn end(&self) -> Option<Position> {
+        let message = self.messages.len().checked_sub(1)?;
+        Some(Position {
+            message,
+            line: self.rows(message) - 1,
+        })
+    }
+
+    // Bottom row when the first message is at the top of the view, so scrolling up can't
+    // leave a gap above it; None when everything fits without scrolling
+    fn highest(&self, end: Position) -> Option<Position> {
+        let top = Position {
+            message: 0,
+            line: 0,
+        };
+        Some(self.walk_down(top, self.page_height() - 1)).filter(|highest| *highest < end)
+    }
+
+    // Keeps a remembered row inside its message after a resize shortened it
+    fn clamp(&self, position: Position) -> Position {
+        Position {
+            line: position.line.min(self.rows(position.message) - 1),
+            ..position
+        }
+    }
+
+    fn previous(&self, position: Position) -> Option<Position> {
+        if position.line > 0 {
+            return Some(Position {
+                line: position.line - 1,
+                ..position
+            });
+        }
+        let message = position.message.checked_sub(1)?;
+        Some(Position {
+            message,
+            line: self.rows(message) - 1,
+        })
+    }
+
+    fn next(&self, position: Position) -> Option<Position> {
+        if position.line + 1 < self.rows(position.message) {
+            return Some(Position {
+                line: position.line + 1,
+                ..position
+            });
+        }
+        (position.message + 1 < self.messages.len()).then_some(Position {
+            message: position.message + 1,
+            line: 0,
+        })
+    }
+
+    // Moves up to `lines` rows up, stopping at the first row
+    fn walk_up(&self, position: Position, mut lines: usize) -> Position {
+        let mut position = self.clamp(position);
+        while lines > 0 {
+            if position.line >= lines {
+                position.line -= lines;
+                break;
+            }
+            lines -= position.line + 1;
+            match self.previous(Position {
+                line: 0,
+                ..position
+            }) {
+                Some(previous) => position = previous,
+                None => {
+                    position.line = 0;
+                    break;
+                }
+            }
+        }
+        position
+    }
+
+    // Moves up to `lines` rows down, stopping at the last row
+    fn walk_down(&self, position: Position, mut lines: usize) -> Position {
+        let mut position = self.clamp(position);
+        while lines > 0 {
+            let last = self.rows(position.message) - 1;
+            if last - position.line >= lines {
+                position.line += lines;
+                break;
+            }
+            lines -= last - position.line + 1;
+            match self.next(Position {
+                line: last,
+                ..position
+            }) {
+                Some(next) => position = next,
+                None => {
+                    position.line = last;
+                    break;
+                }
+            }
+        }
+        position
+    }
+}
+
+// Lays out one message as rows `width` columns wide: a spacer row, the centered notice or
+// Markdown bubble, and another spacer. Bubble text wraps at `max_width` at most. Everything
+// is measured in display columns, so wide CJK text, emoji and combining marks line up like
+// ASCII does.
+fn bubble_layout(
+    message: &Message,
+    width: usize,
+    max_width: usize,
+    theme: &Theme,
+    styles: &markdown::Styles,
+) -> Layout {
+    let mut lines = vec![Line::from("")];
+    let mut links = Vec::new();
+
+    if message.author == "history_loaded" {
+        // History loaded message - display centered dashed line
+        let line = "--- Recent Messages ---";
+        lines.push(Line::from(vec![
+            Span::raw(" ".repeat(width.saturating_sub(line.width()) / 2)),
+            Span::styled(line, Style::default().fg(theme.faint)),
+        ]));
+    } else if message.author == "system" {
+        // System message - centered, with a space either side inside the highlight
+        for line in markdown::wrap_plain(&message.text, width.saturating_sub(2)) {
+            let padding = width.saturating_sub(line.width() + 2) / 2;
+            lines.push(Line::from(vec![
+                Span::raw(" ".repeat(padding)),
+                Span::styled(
+                    format!(" {} ", line),
+                    Style::default()
+                        .bg(theme.system_background)
+                        .fg(theme.system_text),
+                ),
+            ]));
+        }
+    } else {
+        // Regular user message - Markdown rendered inside the bubble, with the gutter emoji
+        // outside it on the first line and blank gutter below so the bubble's edges line up
+        let (gutter, bubble_style) = if message.from_user {
+            (OWN_GUTTER, Style::default().bg(theme.own_bubble))
+        } else {
+            (OTHER_GUTTER, Style::default().bg(theme.other_bubble))
+        };
+        let bubble_style = bubble_style.fg(theme.bubble_text);
+        let gutter_width = gutter.width();
+        let blank_gutter = " ".repeat(gutter_width);
+        let available_width = width.saturating_sub(gutter_width + 2).min(max_width);
+
+        let rendered = render_body(message, available_width, theme, styles);
+        for (line_idx, line) in rendered.lines.into_iter().enumerate() {
+            let gutter = match line_idx {
+                0 => Span::styled(gutter, Style::default().fg(theme.faint)),
+                _ => Span::raw(blank_gutter.clone()),
+            };
+            let mut line_spans = Vec::new();
+            if message.from_user {
+                let padding = width.saturating_sub(line.width() + 2 + gutter_width);
+                line_spans.push(Span::raw(" ".repeat(padding)));
+            } else {
+                line_spans.push(gutter.clone());
+            }
+
+            // This line's links start just past the bubble's leading space
+            let content_x = line_spans.iter().map(Span::width).sum::<usize>() + 1;
+            links.extend(
+                rendered
+                    .links
+                    .iter()
+                    .filter(|link| link.line == line_idx)
+                    .map(|link| LinkSpan {
+                        line: lines.len(),
+                        column: content_x + link.column,
+                        ..link.clone()
+                    }),
+            );
+
+            line_spans.push(Span::styled(" ", bubble_style));
+            line_spans.extend(line.spans.into_iter().map(|span| {
+                let style = bubble_style.patch(span.style);
+                span.style(style)
+            }));
+            line_spans.push(Span::styled(" ", bubble_style));
+            if message.from_user {
+                line_spans.push(gutter);
+            }
+            lines.push(Line::from(line_spans));
+        }
+    }
+
+    // Add a small margin after each message
+    lines.push(Line::from(""));
+    Layout { lines, links }
+}
+
+// Lays out one message IRC-style: "[14:32] <author> text" with no spacers, and wrapped
+// lines indented to where the text starts. A name too long for that gets a row to itself.
+fn compact_layout(
+    message: &Message,
+    width: usize,
+    theme: &Theme,
+    styles: &markdown::Styles,
+) -> Layout {
+    let clock = Span::styled(
+        format!("[{}] ", time::format_clock(message.timestamp)),
+        Style::default().fg(theme.faint),
+    );
+
+    if !message.is_chat() {
+        let text = match message.author.as_str() {
+            "history_loaded" => "--- Recent Messages ---",
+            _ => message.text.as_str(),
+        };
+        let style = Style::default().fg(theme.muted);
+        let indent = clock.width() + 2;
+        let mut wrapped = markdown::wrap_plain(text, width.saturating_sub(indent));
+        // Every message takes at least a row, which scrolling relies on
+        if wrapped.is_empty() {
+            wrapped.push(String::new());
+        }
+        let lines = wrapped
+            .into_iter()
+            .enumerate()
+            .map(|(index, line)| match index {
+                0 => Line::from(vec![clock.clone(), Span::styled("* ", style), line.into()]),
+                _ => Line::from(format!("{}{}", " ".repeat(indent), line)),
+            })
+            .map(|line| line.style(style))
+            .collect();
+        return Layout {
+            lines,
+            links: Vec::new(),
+        };
+    }
+
+    let mut prefix = vec![
+        clock,
+        Span::styled(
+            format!("<{}>", message.author),
+            Style::default()
+                .fg(theme.user_color(&message.author))
+                .add_modifier(Modifier::BOLD),
+        ),
+        Span::raw(" "),
+    ];
+    let prefix_width = prefix.iter().map(Span::width).sum::<usize>();
+    let indent = prefix_width.min(width / 2);
+    let mut rendered = render_body(message, width.saturating_sub(indent), theme, styles);
+    if rendered.lines.is_empty() {
+        rendered.lines.push(Line::default());
+    }
+
+    let mut lines = Vec::new();
+    if prefix_width > indent {
+        lines.push(Line::from(std::mem::take(&mut prefix)));
+    }
+    let first_line = lines.len();
+    let links = rendered
+        .links
+        .iter()
+        .map(|link| LinkSpan {
+            line: first_line + link.line,
+            column: indent + link.column,
+            ..link.clone()
+        })
+        .collect();
+    for line in rendered.lines {
+        let mut spans = match prefix.is_empty() {
+            true => vec![Span::raw(" ".repeat(indent))],
+            false => std::mem::take(&mut prefix),
+        };
+        spans.extend(line.spans);
+        lines.push(Line::from(spans).style(Style::default().fg(theme.text)));
+    }
+    Layout { lines, links }
+}
+
+// The Markdown of a message at `width`, or a tombstone once it's deleted, with a faint
+// mark when it was edited
+fn render_body(
+    message: &Message,
+    width: usize,
+    theme: &Theme,
+    styles: &markdown::Styles,
+) -> markdown::Rendered {
+    let mut rendered = match message.deleted {
+        true => markdown::render("🗑️ *message deleted*", width, styles),
+        false => markdown::render(&message.text, width, styles),
+    };
+    if message.edited && !message.deleted {
+        let mark = Span::styled(EDITED_MARK, Style::default().fg(theme.faint));
+        match rendered.lines.last_mut() {
+            Some(last) if last.width() + EDITED_MARK.width() <= width => last.spans.push(mark),
+            _ => rendered.lines.push(Line::from(mark)),
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: usize = 30;
+
+    fn lay_out(text: &str, author: &str, from_user: bool) -> Vec<Line<'static>> {
+        let message = Message {
+            id: 0,
+            server_id: None,
+            text: text.to_string(),
+            author: author.to_string(),
+            from_user,
+            timestamp: 0,
+            edited: false,
+            deleted: false,
+        };
+        bubble_layout(
+            &message,
+            WIDTH,
+            usize::MAX,
+            &Theme::dark(),
+            &markdown::Styles::default(),
+        )
+        .lines
+    }
+
+    // The rows between the spacers
+    fn content(lines: &[Line<'static>]) -> Vec<String> {
+        lines[1..lines.len() - 1]
+            .iter()
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    // Rows of a right-aligned bubble all end exactly at the right edge
+    fn assert_flush_right(text: &str) {
+        let lines = lay_out(text, "me", true);
+        for line in &lines[1..lines.len() - 1] {
+            assert_eq!(line.width(), WIDTH, "{:?}", line.to_string());
+        }
+    }
+
+    // Rows of a left-aligned bubble never run past the right edge
+    fn assert_fits(text: &str) {
+        for line in content(&lay_out(text, "them", false)) {
+            assert!(line.width() <= WIDTH, "{:?} is {} wide", line, line.width());
+        }
+    }
+
+    #[test]
+    fn cjk_bubbles_align_by_display_width() {
+        assert_flush_right("你好世界");
+        assert_flush_right("日本語のテキストはとても長いので折り返す必要があります");
+        assert_fits("日本語のテキストはとても長いので折り返す必要があります");
+
+        let lines = content(&lay_out("한국어", "me", true));
+        // Six columns of text, two of bubble padding and four of gutter
+        assert_eq!(
+            lines[0],
+            format!("{} 한국어 {}", " ".repeat(WIDTH - 12), OWN_GUTTER)
+        );
+    }
+
+    #[test]
+    fn zwj_sequences_count_as_one_wide_glyph() {
+        let family = "👨‍👩‍👧";
+        assert_flush_right(family);
+        assert_flush_right("🏳️‍🌈 and 👍🏽 and 👨‍👩‍👧‍👦");
+
+        let lines = content(&lay_out(family, "me", true));
+        assert_eq!(
+            lines[0],
+            format!("{} {} {}", " ".repeat(WIDTH - 8), family, OWN_GUTTER)
+        );
+
+        // A long run of them wraps between sequences, never inside one
+        let text = family.repeat(20);
+        assert_fits(&text);
+        let lines = content(&lay_out(&text, "them", false));
+        let families: usize = lines.iter().map(|line| line.matches(family).count()).sum();
+        assert_eq!(families, 20);
+    }
+
+    #[test]
+    fn combining_marks_take_no_columns() {
+        let text = "cafe\u{301} nai\u{308}ve";
+        assert_flush_right(text);
+        let lines = content(&lay_out(text, "me", true));
+        assert_eq!(
+            lines[0],
+            format!("{} {} {}", " ".repeat(WIDTH - 16), text, OWN_GUTTER)
+        );
+
+        // Broken words keep each accent with its letter
+        let text = "e\u{301}".repeat(40);
+        assert_fits(&text);
+        for line in content(&lay_out(&text, "them", false)) {
+            assert!(!line.trim_start().starts_with('\u{301}'), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn system_messages_center_by_display_width() {
+        for text in [
+            "王小明 left the chat",
+            "👨‍👩‍👧 joined",
+            "Zoe\u{308} left the chat",
+        ] {
+            let lines = content(&lay_out(text, "system", false));
+            let padding = (WIDTH - text.width() - 2) / 2;
+            assert_eq!(lines[0], format!("{} {} ", " ".repeat(padding), text));
+        }
+    }
+
+    #[test]
+    fn long_system_messages_wrap_by_display_width() {
+        let text = "会话已结束，所有参与者都已离开聊天室，请稍后再试";
+        let lines = content(&lay_out(text, "system", false));
+        assert!(lines.len() > 1);
+        for line in lines {
+            assert!(line.width() <= WIDTH, "{:?} is {} wide", line, line.width());
+        }
+    }
+}
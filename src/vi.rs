@@ -0,0 +1,521 @@
+use std::ops::Range;
+
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::LineEditor;
+
+// What the app has to do after a key in vi normal or visual mode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    None,
+    // Switch to insert mode (InputMode::Editing)
+    Insert,
+    ScrollUp(usize),
+    ScrollDown(usize),
+    HalfPageUp,
+    HalfPageDown,
+    Top,
+    Bottom,
+    Submit,
+    // Not a vi key; the app's own normal-mode bindings can have it
+    Unhandled,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Operator {
+    Delete,
+    Change,
+    Yank,
+}
+
+// Vi normal/visual mode state for the input box: pending count and operator,
+// visual selection and the unnamed register
+#[derive(Default)]
+pub struct Vi {
+    count: Option<usize>,
+    operator: Option<Operator>,
+    // Count typed before the operator, multiplied into the motion's: 2d3w deletes 6 words
+    operator_count: Option<usize>,
+    // Saw one `g`, waiting for the second
+    pending_g: bool,
+    // Where visual mode started, while it's on
+    visual_anchor: Option<usize>,
+    register: String,
+    // The register holds whole lines, from dd or yy
+    register_linewise: bool,
+}
+
+impl Vi {
+    pub fn is_visual(&self) -> bool {
+        self.visual_anchor.is_some()
+    }
+
+    // Bytes covered by the visual selection, including the character under the cursor
+    pub fn selection(&self, editor: &LineEditor) -> Option<Range<usize>> {
+        // The text can shrink under the anchor, e.g. when it's sent
+        let anchor = editor.text().floor_char_boundary(self.visual_anchor?);
+        let cursor = editor.cursor();
+        let (start, end) = (anchor.min(cursor), anchor.max(cursor));
+        Some(start..editor.next_boundary(end))
+    }
+
+    // Forgets anything half-typed, e.g. when insert mode starts
+    pub fn reset(&mut self) {
+        self.count = None;
+        self.operator = None;
+        self.operator_count = None;
+        self.pending_g = false;
+        self.visual_anchor = None;
+    }
+
+    pub fn handle_key(&mut self, editor: &mut LineEditor, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        if let KeyCode::Char(digit @ '0'..='9') = key.code
+            && !ctrl
+            && (digit != '0' || self.count.is_some())
+        {
+            let digit = digit as usize - '0' as usize;
+            self.count = Some(self.count.unwrap_or(0).saturating_mul(10) + digit);
+            return Action::None;
+        }
+        let count = self
+            .count
+            .take()
+            .unwrap_or(1)
+            .saturating_mul(self.operator_count.take().unwrap_or(1));
+
+        if std::mem::take(&mut self.pending_g) {
+            return match key.code {
+                KeyCode::Char('g') => Action::Top,
+                _ => Action::None,
+            };
+        }
+
+        if let Some((target, inclusive)) = motion(editor, key.code, count, self.operator) {
+            let Some(operator) = self.operator.take() else {
+                editor.move_to(target);
+                clamp_to_line(editor);
+                return Action::None;
+            };
+            let (start, end) = (editor.cursor().min(target), editor.cursor().max(target));
+            let end = if inclusive {
+                editor.next_boundary(end)
+            } else {
+                end
+            };
+            return self.apply(editor, operator, start..end);
+        }
+
+        let operator = match key.code {
+            KeyCode::Char('d') if !ctrl => Some(Operator::Delete),
+            KeyCode::Char('c') if !ctrl => Some(Operator::Change),
+            KeyCode::Char('y') if !ctrl => Some(Operator::Yank),
+            _ => None,
+        };
+        if let Some(operator) = operator {
+            if let Some(selection) = self.selection(editor) {
+                self.visual_anchor = None;
+                return self.apply(editor, operator, selection);
+            }
+            // Doubled operators work on whole lines: dd, cc, yy
+            if self.operator.take() == Some(operator) {
+                return self.apply_lines(editor, operator, count);
+            }
+            self.operator = Some(operator);
+            self.operator_count = Some(count);
+            return Action::None;
+        }
+        // Anything that isn't a motion cancels a pending operator
+        if self.operator.take().is_some() {
+            return Action::None;
+        }
+
+        match key.code {
+            KeyCode::Char('d') if ctrl => Action::HalfPageDown,
+            KeyCode::Char('u') if ctrl => Action::HalfPageUp,
+            KeyCode::Char('r') if ctrl => {
+                self.visual_anchor = None;
+                (0..count).for_each(|_| editor.redo());
+                clamp_to_line(editor);
+                Action::None
+            }
+            KeyCode::Char('x') | KeyCode::Delete => {
+                let range = match self.selection(editor) {
+                    Some(selection) => selection,
+                    None => {
+                        let end = (0..count).fold(editor.cursor(), |at, _| {
+                            editor
+                                .next_boundary(at)
+                                .min(editor.line_end(editor.cursor()))
+                        });
+                        editor.cursor()..end
+                    }
+                };
+                self.visual_anchor = None;
+                self.apply(editor, Operator::Delete, range)
+            }
+            KeyCode::Char('p') => {
+                self.visual_anchor = None;
+                self.put(editor, count, true);
+                Action::None
+            }
+            KeyCode::Char('P') => {
+                self.visual_anchor = None;
+                self.put(editor, count, false);
+                Action::None
+            }
+            KeyCode::Char('u') => {
+                self.visual_anchor = None;
+                (0..count).for_each(|_| editor.undo());
+                clamp_to_line(editor);
+                Action::None
+            }
+            KeyCode::Char('i') => self.insert(),
+            KeyCode::Char('a') => {
+                if editor.cursor() < editor.line_end(editor.cursor()) {
+                    editor.move_to(editor.next_boundary(editor.cursor()));
+                }
+                self.insert()
+            }
+            KeyCode::Char('A') => {
+                editor.move_to(editor.line_end(editor.cursor()));
+                self.insert()
+            }
+            KeyCode::Char('I') => {
+                editor.move_to(first_non_blank(editor));
+                self.insert()
+            }
+            KeyCode::Char('o') => {
+                editor.move_to(editor.line_end(editor.cursor()));
+                editor.insert_newline();
+                self.insert()
+            }
+            KeyCode::Char('O') => {
+                let start = editor.line_start(editor.cursor());
+                editor.move_to(start);
+                editor.insert_newline();
+                editor.move_to(start);
+                self.insert()
+            }
+            KeyCode::Char('v') => {
+                self.visual_anchor = match self.visual_anchor {
+                    Some(_) => None,
+                    None => Some(editor.cursor()),
+                };
+                Action::None
+            }
+            KeyCode::Esc => {
+                self.visual_anchor = None;
+                Action::None
+            }
+            KeyCode::Char('j') | KeyCode::Down => Action::ScrollDown(count),
+            KeyCode::Char('k') | KeyCode::Up => Action::ScrollUp(count),
+            KeyCode::Char('g') => {
+                self.pending_g = true;
+                Action::None
+            }
+            KeyCode::Char('G') => Action::Bottom,
+            // Sending empties the input out from under any selection
+            KeyCode::Enter => {
+                self.reset();
+                Action::Submit
+            }
+            _ => Action::Unhandled,
+        }
+    }
+
+    fn insert(&mut self) -> Action {
+        self.reset();
+        Action::Insert
+    }
+
+    fn apply(
+        &mut self,
+        editor: &mut LineEditor,
+        operator: Operator,
+        range: Range<usize>,
+    ) -> Action {
+        if !range.is_empty() {
+            self.register = editor.text()[range.clone()].to_string();
+            self.register_linewise = false;
+        }
+        match operator {
+            Operator::Yank => {
+                editor.move_to(range.start);
+                Action::None
+            }
+            Operator::Delete => {
+                editor.delete_range(range.start, range.end);
+                clamp_to_line(editor);
+                Action::None
+            }
+            Operator::Change => {
+                editor.delete_range(range.start, range.end);
+                self.insert()
+            }
+        }
+    }
+
+    // dd/cc/yy over `count` lines starting at the cursor's
+    fn apply_lines(&mut self, editor: &mut LineEditor, operator: Operator, count: usize) -> Action {
+        let start = editor.line_start(editor.cursor());
+        let end = (1..count).fold(editor.line_end(start), |end, _| {
+            editor.line_end((end + 1).min(editor.text().len()))
+        });
+        self.register = editor.text()[start..end].to_string();
+        self.register_linewise = true;
+        match operator {
+            Operator::Yank => Action::None,
+            // cc keeps the line itself and replaces what's on it
+            Operator::Change => {
+                editor.delete_range(start, end);
+                self.insert()
+            }
+            Operator::Delete => {
+                // Take a line break along, the one after unless this is the last line
+                let (start, end) = if end < editor.text().len() {
+                    (start, end + 1)
+                } else {
+                    (start.saturating_sub(1), end)
+                };
+                editor.delete_range(start, end);
+                editor.move_to(first_non_blank(editor));
+                Action::None
+            }
+        }
+    }
+
+    // p/P: puts the register after/before the cursor, or below/above the line for whole lines
+    fn put(&self, editor: &mut LineEditor, count: usize, after: bool) {
+        if self.register.is_empty() {
+            return;
+        }
+        let text = vec![self.register.as_str(); count];
+        if self.register_linewise {
+            let text = text.join("\n");
+            if after {
+                editor.move_to(editor.line_end(editor.cursor()));
+                editor.insert_str(&format!("\n{}", text));
+                editor.move_to(editor.line_start(editor.cursor()));
+            } else {
+                let start = editor.line_start(editor.cursor());
+                editor.move_to(start);
+                editor.insert_str(&format!("{}\n", text));
+                editor.move_to(start);
+            }
+        } else {
+            if after && editor.cursor() < editor.line_end(editor.cursor()) {
+                editor.move_to(editor.next_boundary(editor.cursor()));
+            }
+            editor.insert_str(&text.concat());
+            // Like vi, land on the last character put
+            editor.move_to(editor.previous_boundary(editor.cursor()));
+        }
+    }
+}
+
+// Whether normal mode keeps `key` for vi, so the app's own bindings would never see it
+pub fn takes(key: KeyEvent) -> bool {
+    Vi::default().handle_key(&mut LineEditor::new(), key) != Action::Unhandled
+}
+
+// Esc from insert mode steps back onto the character just typed
+pub fn leave_insert(editor: &mut LineEditor) {
+    if editor.cursor() > editor.line_start(editor.cursor()) {
+        editor.move_to(editor.previous_boundary(editor.cursor()));
+    }
+}
+
+// Where a motion key takes the cursor, and whether an operator should include the
+// character it lands on
+fn motion(
+    editor: &LineEditor,
+    code: KeyCode,
+    count: usize,
+    operator: Option<Operator>,
+) -> Option<(usize, bool)> {
+    let text = editor.text();
+    let cursor = editor.cursor();
+    let repeat = |step: &dyn Fn(usize) -> usize| (0..count).fold(cursor, |at, _| step(at));
+
+    Some(match code {
+        KeyCode::Char('h') | KeyCode::Left | KeyCode::Backspace => (
+            repeat(&|at| editor.previous_boundary(at).max(editor.line_start(cursor))),
+            false,
+        ),
+        KeyCode::Char('l') | KeyCode::Right | KeyCode::Char(' ') => (
+            repeat(&|at| editor.next_boundary(at).min(editor.line_end(cursor))),
+            false,
+        ),
+        KeyCode::Char('0') | KeyCode::Home => (editor.line_start(cursor), false),
+        KeyCode::Char('^') => (first_non_blank(editor), false),
+        KeyCode::Char('$') | KeyCode::End => (editor.line_end(cursor), false),
+        // cw changes to the end of the word, not up to the next one
+        KeyCode::Char('w')
+            if operator == Some(Operator::Change)
+                && text[cursor..]
+                    .chars()
+                    .next()
+                    .is_some_and(|c| !c.is_whitespace()) =>
+        {
+            (repeat(&|at| word_end(text, at, at == cursor)), true)
+        }
+        KeyCode::Char('w') => (repeat(&|at| word_forward(text, at)), false),
+        KeyCode::Char('b') => (repeat(&|at| word_backward(text, at)), false),
+        KeyCode::Char('e') => (repeat(&|at| word_end(text, at, false)), true),
+        _ => return None,
+    })
+}
+
+// Vi's word classes: blanks, keyword characters and other punctuation
+fn class(grapheme: &str) -> u8 {
+    match grapheme.chars().next() {
+        Some(c) if c.is_whitespace() => 0,
+        Some(c) if c.is_alphanumeric() || c == '_' => 1,
+        _ => 2,
+    }
+}
+
+// w: start of the next word
+fn word_forward(text: &str, from: usize) -> usize {
+    let mut graphemes = text[from..].grapheme_indices(true).peekable();
+    let Some((_, first)) = graphemes.next() else {
+        return from;
+    };
+    let mut index = from + first.len();
+    let first_class = class(first);
+    while let Some((_, g)) = graphemes.next_if(|(_, g)| first_class != 0 && class(g) == first_class)
+    {
+        index += g.len();
+    }
+    while let Some((_, g)) = graphemes.next_if(|(_, g)| class(g) == 0) {
+        index += g.len();
+    }
+    index
+}
+
+// b: start of this word, or the previous one when already there
+fn word_backward(text: &str, from: usize) -> usize {
+    let mut graphemes = text[..from].grapheme_indices(true).rev().peekable();
+    let mut index = from;
+    while let Some((start, _)) = graphemes.next_if(|(_, g)| class(g) == 0) {
+        index = start;
+    }
+    let Some(&(_, g)) = graphemes.peek() else {
+        return index;
+    };
+    let word_class = class(g);
+    while let Some((start, _)) = graphemes.next_if(|(_, g)| class(g) == word_class) {
+        index = start;
+    }
+    index
+}
+
+// e: last character of this word, or of the next one when already there.
+// `stay` lets a word end under the cursor count, for cw.
+fn word_end(text: &str, from: usize, stay: bool) -> usize {
+    let graphemes: Vec<(usize, &str)> = text[from..]
+        .grapheme_indices(true)
+        .map(|(index, g)| (from + index, g))
+        .collect();
+    let mut i = if stay { 0 } else { 1 };
+    while graphemes.get(i).is_some_and(|(_, g)| class(g) == 0) {
+        i += 1;
+    }
+    let Some(&(_, g)) = graphemes.get(i) else {
+        return graphemes.last().map_or(from, |(index, _)| *index);
+    };
+    let word_class = class(g);
+    while graphemes
+        .get(i + 1)
+        .is_some_and(|(_, g)| class(g) == word_class)
+    {
+        i += 1;
+    }
+    graphemes[i].0
+}
+
+fn first_non_blank(editor: &LineEditor) -> usize {
+    let start = editor.line_start(editor.cursor());
+    let line = &editor.text()[start..editor.line_end(start)];
+    start + (line.len() - line.trim_start().len())
+}
+
+// Normal mode sits on a character, never past the end of the line
+fn clamp_to_line(editor: &mut LineEditor) {
+    let cursor = editor.cursor();
+    if cursor == editor.line_end(cursor) && cursor > editor.line_start(cursor) {
+        editor.move_to(editor.previous_boundary(cursor));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(vi: &mut Vi, editor: &mut LineEditor, keys: &str) -> Action {
+        let mut action = Action::None;
+        for c in keys.chars() {
+            let code = match c {
+                '\n' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+            action = vi.handle_key(editor, KeyEvent::new(code, KeyModifiers::NONE));
+        }
+        action
+    }
+
+    fn editor(text: &str) -> LineEditor {
+        editor_at(text, 0)
+    }
+
+    fn editor_at(text: &str, cursor: usize) -> LineEditor {
+        let mut editor = LineEditor::new();
+        editor.set_text(text, cursor);
+        editor
+    }
+
+    #[test]
+    fn submitting_from_visual_mode_drops_the_selection() {
+        let mut vi = Vi::default();
+        let mut editor = editor("hello world");
+        assert_eq!(press(&mut vi, &mut editor, "0$v\n"), Action::Submit);
+        editor.take();
+        assert!(!vi.is_visual());
+        assert_eq!(vi.selection(&editor), None);
+    }
+
+    #[test]
+    fn undo_and_put_drop_the_selection() {
+        for keys in ["u", "p", "P"] {
+            let mut vi = Vi::default();
+            let mut editor = editor("hello world");
+            press(&mut vi, &mut editor, "yw$v");
+            press(&mut vi, &mut editor, keys);
+            assert!(!vi.is_visual(), "{}", keys);
+        }
+    }
+
+    #[test]
+    fn counts_before_and_after_an_operator_multiply() {
+        let mut vi = Vi::default();
+        let mut editor = editor("a b c d e f g h i j");
+        press(&mut vi, &mut editor, "2d3w");
+        assert_eq!(editor.text(), "g h i j");
+
+        let mut editor = editor_at("one two three", 8);
+        press(&mut vi, &mut editor, "d0");
+        assert_eq!(editor.text(), "three");
+    }
+
+    #[test]
+    fn selection_survives_the_text_shrinking() {
+        let mut vi = Vi::default();
+        let mut editor = editor("hello world");
+        press(&mut vi, &mut editor, "$v");
+        editor.set_text("hi", 0);
+        assert_eq!(vi.selection(&editor), Some(0..2));
+    }
+}